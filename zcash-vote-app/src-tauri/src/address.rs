@@ -3,16 +3,18 @@ use std::sync::Mutex;
 use anyhow::{Error, Result};
 use orchard::keys::Scope;
 use tauri::State;
-use zcash_vote::{address::VoteAddress, decrypt::to_fvk};
+use zcash_vote::{address::VoteAddress, errors::VoteError};
 
 use crate::state::AppState;
 
 #[tauri::command]
-pub fn get_address(state: State<Mutex<AppState>>) -> Result<String, String> {
+pub fn get_address(state: State<Mutex<AppState>>) -> Result<String, VoteError> {
     tauri_export!(state, _connection, {
-        let fvk = to_fvk(&state.key)?;
+        let network = state.election.network;
+        let fvks = state.fvks()?;
+        let (_, fvk) = &fvks[0];
         let address = fvk.address_at(0u64, Scope::External);
-        let vote_address = VoteAddress(address);
+        let vote_address = VoteAddress(address, network);
         Ok::<_, Error>(vote_address.to_string())
     })
 }
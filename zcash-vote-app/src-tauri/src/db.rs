@@ -1,80 +1,463 @@
 use std::sync::Mutex;
 
 use anyhow::{Error, Result};
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension as _};
 use tauri::State;
 use zcash_vote::{
-    db::{load_prop, store_prop},
+    client::SubmissionStatus,
+    db::{create_schema, delete_prop, load_prop, store_prop},
     election::Election,
+    errors::VoteError,
+    migration::{migrate, Migration},
 };
 use orchard::{keys::Scope, vote::Ballot};
 
-use crate::state::AppState;
+use crate::{
+    encrypt::{decrypt_secret, encrypt_secret, EncryptedSecret, Secret},
+    state::AppState,
+};
 
-pub fn store_election(
-    connection: &Connection,
-    url: &str,
-    election: &Election,
-    key: &str,
-    internal: &str,
-) -> Result<()> {
-    store_prop(connection, "url", url)?;
-    store_prop(
-        connection,
-        "election",
-        &serde_json::to_string(election).unwrap(),
+/// Property that holds the version of the app tables
+pub const APP_SCHEMA_VERSION: &str = "app_schema_version";
+
+/// Migrations of the app tables, applied after the base schema
+pub const APP_MIGRATIONS: &[Migration] = &[
+    migration_001,
+    migration_002,
+    migration_003,
+    migration_004,
+];
+
+pub fn create_app_schema(connection: &Connection) -> Result<()> {
+    create_schema(connection)?;
+    migrate(connection, APP_SCHEMA_VERSION, APP_MIGRATIONS)?;
+    Ok(())
+}
+
+fn migration_001(connection: &Connection) -> Result<()> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS votes(
+        id_vote INTEGER PRIMARY KEY,
+        hash TEXT NOT NULL,
+        address TEXT NOT NULL,
+        amount INTEGER NOT NULL)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn migration_002(connection: &Connection) -> Result<()> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS elections(
+        id_election INTEGER PRIMARY KEY,
+        id TEXT NOT NULL UNIQUE,
+        urls TEXT NOT NULL,
+        definition TEXT NOT NULL,
+        height INTEGER,
+        nf_root TEXT,
+        cmx_root TEXT)",
+        [],
+    )?;
+    connection.execute(
+        "ALTER TABLE votes ADD COLUMN election INTEGER NOT NULL DEFAULT 0",
+        [],
+    )?;
+
+    // Single election databases kept the election in the properties
+    // and used 0 as the election key in every table
+    if let Some(definition) = load_prop(connection, "election")? {
+        let election: Election = serde_json::from_str(&definition)?;
+        let urls = load_prop(connection, "url")?.unwrap_or_default();
+        let height = load_prop(connection, "height")?
+            .map(|h| h.parse::<u32>())
+            .transpose()?;
+        connection.execute(
+            "INSERT INTO elections(id_election, id, urls, definition, height, nf_root, cmx_root)
+            VALUES (0, ?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                election.id(),
+                urls,
+                definition,
+                height,
+                load_prop(connection, "nf_root")?,
+                load_prop(connection, "cmx_root")?
+            ],
+        )?;
+        for name in ["election", "url", "height", "nf_root", "cmx_root"] {
+            delete_prop(connection, name)?;
+        }
+        store_prop(connection, "current_election", &election.id())?;
+    }
+    Ok(())
+}
+
+/// Votes are tracked until the server includes their ballot.
+/// Votes made before were recorded as final
+fn migration_003(connection: &Connection) -> Result<()> {
+    connection.execute_batch(
+        "ALTER TABLE votes ADD COLUMN status TEXT NOT NULL DEFAULT 'included';
+        ALTER TABLE votes ADD COLUMN height INTEGER;
+        ALTER TABLE votes ADD COLUMN reason TEXT;
+        ALTER TABLE votes ADD COLUMN ballot TEXT;",
     )?;
-    store_prop(connection, "key", key)?;
-    store_prop(connection, "internal", internal)?;
     Ok(())
 }
 
-pub fn load_election(connection: &Connection) -> Result<(Vec<String>, Election, String, Scope)> {
-    let urls = load_prop(connection, "url")?.expect("Missing URL");
-    let url = urls.split(",").into_iter().map(String::from).collect();
-    let election = load_prop(connection, "election")?.expect("Missing election property");
-    let key = load_prop(connection, "key")?.expect("Missing wallet key");
+/// The ballots of an allocation are submitted together. They refer
+/// to the first vote of the allocation, earlier votes have no batch
+fn migration_004(connection: &Connection) -> Result<()> {
+    connection.execute("ALTER TABLE votes ADD COLUMN batch INTEGER", [])?;
+    Ok(())
+}
+
+pub fn store_wallet(connection: &Connection, internal: bool, accounts: &[u32]) -> Result<()> {
+    let accounts = accounts
+        .iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    store_prop(connection, "internal", if internal { "true" } else { "false" })?;
+    store_prop(connection, "accounts", &accounts)?;
+    Ok(())
+}
+
+pub fn load_wallet(connection: &Connection) -> Result<(Scope, Vec<u32>)> {
     let internal = load_prop(connection, "internal")?.unwrap_or("false".to_string());
-    let election: Election = serde_json::from_str(&election)?;
     let scope = if internal == "true" {
         Scope::Internal
     } else {
         Scope::External
     };
-    Ok((url, election, key, scope))
+    let accounts = load_prop(connection, "accounts")?.unwrap_or("0".to_string());
+    let accounts = accounts
+        .split(",")
+        .map(|a| a.parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((scope, accounts))
+}
+
+pub fn store_election(connection: &Connection, urls: &str, election: &Election) -> Result<u32> {
+    let id_election = connection.query_row(
+        "INSERT INTO elections(id, urls, definition)
+        VALUES (?1, ?2, ?3)
+        ON CONFLICT DO UPDATE SET
+        urls = excluded.urls,
+        definition = excluded.definition
+        RETURNING id_election",
+        params![election.id(), urls, serde_json::to_string(election)?],
+        |r| r.get::<_, u32>(0),
+    )?;
+    Ok(id_election)
+}
+
+pub fn load_election(connection: &Connection, id: &str) -> Result<(u32, Vec<String>, Election)> {
+    let (id_election, urls, definition) = connection
+        .query_row(
+            "SELECT id_election, urls, definition FROM elections WHERE id = ?1",
+            [id],
+            |r| {
+                Ok((
+                    r.get::<_, u32>(0)?,
+                    r.get::<_, String>(1)?,
+                    r.get::<_, String>(2)?,
+                ))
+            },
+        )
+        .optional()?
+        .ok_or(anyhow::anyhow!("Unknown election {id}"))?;
+    let urls = urls.split(",").into_iter().map(String::from).collect();
+    let election: Election = serde_json::from_str(&definition)?;
+    Ok((id_election, urls, election))
+}
+
+pub fn list_elections(connection: &Connection) -> Result<Vec<Election>> {
+    let mut s = connection.prepare("SELECT definition FROM elections ORDER BY id_election")?;
+    let rows = s.query_map([], |r| r.get::<_, String>(0))?;
+    let mut elections = vec![];
+    for r in rows {
+        elections.push(serde_json::from_str::<Election>(&r?)?);
+    }
+    Ok(elections)
+}
+
+/// Remove an election with its notes, ballots, votes and reference data
+pub fn delete_election(connection: &Connection, id_election: u32) -> Result<()> {
+    for table in [
+        "notes",
+        "ballots",
+        "votes",
+        "nfs",
+        "dnfs",
+        "cmxs",
+        "cmx_roots",
+        "cmx_frontiers",
+    ] {
+        connection.execute(
+            &format!("DELETE FROM {table} WHERE election = ?1"),
+            [id_election],
+        )?;
+    }
+    connection.execute(
+        "DELETE FROM elections WHERE id_election = ?1",
+        [id_election],
+    )?;
+    Ok(())
+}
+
+pub fn store_sync_height(connection: &Connection, id_election: u32, height: u32) -> Result<()> {
+    connection.execute(
+        "UPDATE elections SET height = ?2 WHERE id_election = ?1",
+        params![id_election, height],
+    )?;
+    Ok(())
+}
+
+pub fn load_sync_height(connection: &Connection, id_election: u32) -> Result<Option<u32>> {
+    let height = connection.query_row(
+        "SELECT height FROM elections WHERE id_election = ?1",
+        [id_election],
+        |r| r.get::<_, Option<u32>>(0),
+    )?;
+    Ok(height)
+}
+
+/// Version of the storage of the wallet secrets. Version 0 keeps the
+/// key and passphrase in plain text, version 1 encrypts them with the
+/// wallet password
+pub const SECRET_VERSION: u32 = 1;
+
+pub fn load_secret_version(connection: &Connection) -> Result<u32> {
+    if let Some(version) = load_prop(connection, "secret_version")? {
+        return Ok(version.parse()?);
+    }
+    let version = match load_prop(connection, "encrypted_key")? {
+        Some(_) => 1,
+        None => 0,
+    };
+    Ok(version)
+}
+
+/// Store the wallet key and passphrase encrypted with the password
+/// and remove any plain text copy
+pub fn store_secret(connection: &Connection, password: &str, secret: &Secret) -> Result<()> {
+    let encrypted = encrypt_secret(password, secret)?;
+    store_prop(connection, "encrypted_key", &serde_json::to_string(&encrypted)?)?;
+    store_prop(connection, "secret_version", &SECRET_VERSION.to_string())?;
+    delete_prop(connection, "key")?;
+    delete_prop(connection, "passphrase")?;
+    Ok(())
+}
+
+/// Encrypt the plain text key and passphrase of a database from before
+/// version 1 with a new password. The deleted values are overwritten
+/// and the file is rebuilt, so that no page keeps them
+pub fn migrate_secret(connection: &Connection, password: &str) -> Result<()> {
+    if load_secret_version(connection)? >= SECRET_VERSION {
+        anyhow::bail!("The wallet key is already encrypted");
+    }
+    let key = load_prop(connection, "key")?.ok_or(anyhow::anyhow!("Missing wallet key"))?;
+    let passphrase = load_prop(connection, "passphrase")?.unwrap_or_default();
+    let secret = Secret { key, passphrase };
+    connection.execute_batch("PRAGMA secure_delete = ON")?;
+    let transaction = connection.unchecked_transaction()?;
+    store_secret(&transaction, password, &secret)?;
+    transaction.commit()?;
+    connection.execute_batch("VACUUM")?;
+    Ok(())
+}
+
+/// Decrypt the wallet key and passphrase. Databases from before
+/// version 1 must go through `migrate_secret` first
+pub fn load_secret(connection: &Connection, password: &str) -> Result<Secret> {
+    if load_secret_version(connection)? < SECRET_VERSION {
+        anyhow::bail!("The wallet key is not encrypted, choose a new password");
+    }
+    let encrypted = load_prop(connection, "encrypted_key")?
+        .ok_or(anyhow::anyhow!("Missing wallet key"))?;
+    let encrypted = serde_json::from_str::<EncryptedSecret>(&encrypted)?;
+    decrypt_secret(password, &encrypted)
 }
 
 #[tauri::command]
-pub fn get_prop(name: String, state: State<Mutex<AppState>>) -> Result<Option<String>, String> {
+pub fn get_prop(name: String, state: State<Mutex<AppState>>) -> Result<Option<String>, VoteError> {
     tauri_export!(state, connection, {
         Ok::<_, Error>(load_prop(&connection, &name)?)
     })
 }
 
-pub fn store_ballot(connection: &Connection, height: u32, ballot: &Ballot) -> Result<()> {
+pub fn store_ballot(
+    connection: &Connection,
+    id_election: u32,
+    height: u32,
+    ballot: &Ballot,
+) -> Result<()> {
     let hash = ballot.data.sighash()?;
     let ballot = serde_json::to_string(ballot)?;
     connection.execute(
         "INSERT INTO ballots(election, height, hash, data)
         VALUES (?1, ?2, ?3, ?4)",
-        params![0, height, &hash, &ballot],
+        params![id_election, height, &hash, &ballot],
     )?;
     Ok(())
 }
 
-pub fn mark_spent(connection: &Connection, height: u32, dnf: &[u8]) -> Result<()> {
+pub fn mark_spent(connection: &Connection, id_election: u32, height: u32, dnf: &[u8]) -> Result<()> {
     connection.execute(
-        "UPDATE notes SET spent = ?1 WHERE dnf = ?2",
-        params![height, dnf],
+        "UPDATE notes SET spent = ?2 WHERE election = ?1 AND dnf = ?3",
+        params![id_election, height, dnf],
     )?;
     Ok(())
 }
 
-pub fn store_vote(connection: &Connection, hash: &str, address: &str, amount: u64) -> Result<()> {
+/// Record a vote whose ballot was accepted by the server,
+/// it is pending until the ballot is included. `batch` is the
+/// first vote of the allocation, if it is not this one
+pub fn store_vote(
+    connection: &Connection,
+    id_election: u32,
+    hash: &str,
+    address: &str,
+    amount: u64,
+    ballot: &Ballot,
+    batch: Option<u32>,
+) -> Result<u32> {
+    connection.execute(
+        "INSERT INTO votes(election, hash, address, amount, status, ballot, batch)
+        VALUES (?1, ?2, ?3, ?4, 'pending', ?5, ?6)",
+        params![
+            id_election,
+            hash,
+            address,
+            amount,
+            serde_json::to_string(ballot)?,
+            batch
+        ],
+    )?;
+    Ok(connection.last_insert_rowid() as u32)
+}
+
+pub fn update_vote_status(
+    connection: &Connection,
+    id_vote: u32,
+    status: SubmissionStatus,
+    height: Option<u32>,
+    reason: Option<&str>,
+) -> Result<()> {
+    let status = serde_json::to_value(status)?;
     connection.execute(
-        "INSERT INTO votes(hash, address, amount)
-        VALUES (?1, ?2, ?3)",
-        params![hash, address, amount],
+        "UPDATE votes SET status = ?2, height = ?3, reason = ?4 WHERE id_vote = ?1",
+        params![id_vote, status.as_str(), height, reason],
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use zcash_vote::{
+        db::MIGRATIONS,
+        migration::{get_schema_version, BASELINE_SCHEMA, SCHEMA_VERSION},
+    };
+
+    use super::*;
+
+    #[test]
+    fn single_election_database_migrates_to_head() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(BASELINE_SCHEMA).unwrap();
+        connection
+            .execute(
+                "CREATE TABLE votes(
+                id_vote INTEGER PRIMARY KEY,
+                hash TEXT NOT NULL,
+                address TEXT NOT NULL,
+                amount INTEGER NOT NULL)",
+                [],
+            )
+            .unwrap();
+        let election = Election {
+            name: "baseline".to_string(),
+            ..Election::default()
+        };
+        store_prop(&connection, "election", &serde_json::to_string(&election).unwrap()).unwrap();
+        store_prop(&connection, "url", "https://vote.example.com").unwrap();
+        store_prop(&connection, "height", "2500000").unwrap();
+        store_prop(&connection, "internal", "true").unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO votes(hash, address, amount) VALUES ('ab', 'zvote1', 1000);
+                INSERT INTO notes(election, position, height, txid, value,
+                div, rseed, nf, dnf, rho, spent)
+                VALUES (0, 7, 100, x'06', 2000, x'07', x'08', x'02', x'03', x'09', NULL);",
+            )
+            .unwrap();
+
+        create_app_schema(&connection).unwrap();
+
+        assert_eq!(
+            get_schema_version(&connection, SCHEMA_VERSION).unwrap() as usize,
+            MIGRATIONS.len()
+        );
+        assert_eq!(
+            get_schema_version(&connection, APP_SCHEMA_VERSION).unwrap() as usize,
+            APP_MIGRATIONS.len()
+        );
+        let id = election.id();
+        let (id_election, urls, stored) = load_election(&connection, &id).unwrap();
+        assert_eq!(id_election, 0);
+        assert_eq!(urls, ["https://vote.example.com"]);
+        assert_eq!(stored.name, "baseline");
+        assert_eq!(load_sync_height(&connection, 0).unwrap(), Some(2500000));
+        assert_eq!(load_prop(&connection, "current_election").unwrap(), Some(id));
+        assert_eq!(load_prop(&connection, "election").unwrap(), None);
+        assert_eq!(load_wallet(&connection).unwrap().0, Scope::Internal);
+
+        // earlier votes were final
+        let (election, status, amount, batch) = connection
+            .query_row("SELECT election, status, amount, batch FROM votes", [], |r| {
+                Ok((
+                    r.get::<_, u32>(0)?,
+                    r.get::<_, String>(1)?,
+                    r.get::<_, u64>(2)?,
+                    r.get::<_, Option<u32>>(3)?,
+                ))
+            })
+            .unwrap();
+        assert_eq!((election, status.as_str(), amount, batch), (0, "included", 1000, None));
+        // and the notes were received by the first account
+        let (account, value) = connection
+            .query_row("SELECT account, value FROM notes", [], |r| {
+                Ok((r.get::<_, u32>(0)?, r.get::<_, u64>(1)?))
+            })
+            .unwrap();
+        assert_eq!((account, value), (0, 2000));
+    }
+
+    #[test]
+    fn plain_text_key_is_migrated_with_a_new_password() {
+        let path = std::env::temp_dir().join(format!("zcash-vote-app-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let connection = Connection::open(&path).unwrap();
+        create_app_schema(&connection).unwrap();
+        store_prop(&connection, "key", "seed words").unwrap();
+        store_prop(&connection, "passphrase", "secret passphrase").unwrap();
+        assert_eq!(load_secret_version(&connection).unwrap(), 0);
+        load_secret(&connection, "password").unwrap_err();
+
+        migrate_secret(&connection, "new password").unwrap();
+
+        assert_eq!(load_secret_version(&connection).unwrap(), SECRET_VERSION);
+        assert_eq!(load_prop(&connection, "key").unwrap(), None);
+        assert_eq!(load_prop(&connection, "passphrase").unwrap(), None);
+        load_secret(&connection, "password").unwrap_err();
+        let secret = load_secret(&connection, "new password").unwrap();
+        assert_eq!(secret.key, "seed words");
+        assert_eq!(secret.passphrase, "secret passphrase");
+        migrate_secret(&connection, "new password").unwrap_err();
+        drop(connection);
+
+        // no page of the file has the plain text
+        let file = std::fs::read(&path).unwrap();
+        assert!(!file.windows(10).any(|w| w == b"seed words"));
+        let _ = std::fs::remove_file(&path);
+    }
+}
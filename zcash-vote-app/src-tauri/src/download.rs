@@ -1,48 +1,50 @@
 use std::sync::Mutex;
 
 use anyhow::{Error, Result};
-use orchard::vote::Ballot;
 use rusqlite::OptionalExtension;
 use tauri::{ipc::Channel, State};
 use zcash_vote::{
-    db::{load_prop, store_prop},
-    decrypt::to_fvk,
-    election::Election,
+    client::BallotEvent,
+    errors::VoteError,
 };
 
-use crate::{db::store_ballot, state::AppState, validate::handle_ballot};
+use crate::{
+    db::{store_ballot, store_sync_height},
+    state::AppState,
+    validate::handle_ballot,
+};
 
 #[tauri::command]
-pub async fn http_get(url: String) -> Result<String, String> {
+pub async fn http_get(url: String) -> Result<String, VoteError> {
     let rep = async {
         let rep = reqwest::get(url).await?;
         let body = rep.text().await?;
         Ok::<_, Error>(body)
     };
-    rep.await.map_err(|e| e.to_string())
+    rep.await.map_err(VoteError::from)
 }
 
 #[tauri::command]
 pub async fn download_reference_data(
     state: State<'_, Mutex<AppState>>,
     channel: Channel<u32>,
-) -> Result<(), String> {
+) -> Result<(), VoteError> {
     let r = async {
-        let (connection, election, fvk, scope) = {
+        let (connection, id_election, election, fvks, scope) = {
             let s = state.lock().unwrap();
-            let fvk = to_fvk(&s.key)?;
+            let fvks = s.fvks()?;
             let connection = s.pool.get().unwrap();
             let election = s.election.clone();
             let scope = s.scope;
-            (connection, election, fvk, scope)
+            (connection, s.id_election, election, fvks, scope)
         };
-        let lwd_url = std::env::var("LWD_URL").unwrap_or("https://zec.rocks".to_string());
+        let lwd_url = election.network.lwd_url();
         connection.execute("BEGIN TRANSACTION", [])?;
         let (connection, h) = zcash_vote::download::download_reference_data(
             connection,
-            0,
+            id_election,
             &election,
-            Some(fvk),
+            &fvks,
             scope,
             &lwd_url,
             move |h| {
@@ -50,46 +52,79 @@ pub async fn download_reference_data(
             },
         )
         .await?;
-        store_prop(&connection, "height", &h.to_string()).unwrap();
+        store_sync_height(&connection, id_election, h)?;
         connection.execute("COMMIT", [])?;
         Ok::<_, Error>(())
     };
-    r.await.map_err(|e| e.to_string())
+    r.await.map_err(VoteError::from)
 }
 
 #[tauri::command]
-pub async fn sync(state: State<'_, Mutex<AppState>>) -> Result<(), String> {
+pub async fn sync(state: State<'_, Mutex<AppState>>) -> Result<(), VoteError> {
     let rep = async {
-        let (base_url, pool) = {
+        let ((client, id), pool, id_election, election, fvks) = {
             let s = state.lock().unwrap();
-            let index = rand::random_range(0..s.urls.len());
-            (s.urls[index].clone(), s.pool.clone())
+            (
+                s.client()?,
+                s.pool.clone(),
+                s.id_election,
+                s.election.clone(),
+                s.fvks()?,
+            )
         };
         let connection = pool.get()?;
-        let r = connection.query_row("SELECT 1 FROM cmxs", [], |_| Ok(())).optional()?;
+        let r = connection
+            .query_row(
+                "SELECT 1 FROM cmxs WHERE election = ?1",
+                [id_election],
+                |_| Ok(()),
+            )
+            .optional()?;
         if r.is_none() {
             return Ok::<_, Error>(()); // don't sync if we haven't downloaded blocks
         }
-        let url = format!("{}/num_ballots", base_url);
-        let n = reqwest::get(url).await?.text().await?;
-        let n = n.parse::<u32>()?;
-        let election = load_prop(&connection, "election")?.unwrap();
-        let election = serde_json::from_str::<Election>(&election)?;
-        let c = connection.query_row("SELECT COUNT(*) FROM ballots", [], |r| r.get::<_, u32>(0))?;
-        if c < n {
-            for i in c..n {
-                let url = format!("{}/ballot/height/{}", base_url, i + 1);
-                let ballot = reqwest::get(url).await?.text().await?;
-                let ballot = serde_json::from_str::<Ballot>(&ballot)?;
+        let n = client.num_ballots(&id).await?;
+        let mut c = connection.query_row(
+            "SELECT COUNT(*) FROM ballots WHERE election = ?1",
+            [id_election],
+            |r| r.get::<_, u32>(0),
+        )?;
+        while c < n {
+            let ballots = client.ballot_range(&id, c + 1, n).await?;
+            if ballots.is_empty() {
+                anyhow::bail!("Missing ballot at height {}", c + 1);
+            }
+            for (height, ballot) in ballots {
                 let mut connection = pool.get()?;
                 let transaction = connection.transaction()?;
-                handle_ballot(&transaction, &election, i + 1, &ballot)?;
-                store_ballot(&transaction, i + 1, &ballot)?;
+                handle_ballot(&transaction, id_election, &election, &fvks, height, &ballot)?;
+                store_ballot(&transaction, id_election, height, &ballot)?;
                 transaction.commit()?;
+                c = height;
             }
         }
 
         Ok::<_, Error>(())
     };
-    rep.await.map_err(|e| e.to_string())
+    rep.await.map_err(VoteError::from)
+}
+
+/// Forward the ballots committed by the server, so that the
+/// frontend can sync as they arrive. Returns when the server
+/// closes the stream
+#[tauri::command]
+pub async fn watch_ballot_events(
+    state: State<'_, Mutex<AppState>>,
+    channel: Channel<Option<BallotEvent>>,
+) -> Result<(), VoteError> {
+    let r = async {
+        let (client, id) = state.lock().unwrap().client()?;
+        client
+            .watch_ballots(&id, move |e| {
+                let _ = channel.send(e);
+            })
+            .await?;
+        Ok::<_, Error>(())
+    };
+    r.await.map_err(VoteError::from)
 }
@@ -0,0 +1,73 @@
+use anyhow::{anyhow, Result};
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    XChaCha20Poly1305, XNonce,
+};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Wallet secrets that are only stored encrypted in the database
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct Secret {
+    pub key: String,
+    pub passphrase: String,
+}
+
+/// Secret encrypted with XChaCha20-Poly1305 under a key
+/// derived from the user password with Argon2id
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EncryptedSecret {
+    #[serde(with = "hex")]
+    pub salt: Vec<u8>,
+    #[serde(with = "hex")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "hex")]
+    pub ciphertext: Vec<u8>,
+}
+
+fn derive_key(password: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, key.as_mut())
+        .map_err(|e| anyhow!("Key derivation failed: {e}"))?;
+    Ok(key)
+}
+
+pub fn encrypt_secret(password: &str, secret: &Secret) -> Result<EncryptedSecret> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; 24];
+    OsRng.fill_bytes(&mut nonce);
+
+    let key = derive_key(password, &salt)?;
+    let cipher = XChaCha20Poly1305::new(key.as_ref().into());
+    let plaintext = Zeroizing::new(serde_json::to_vec(secret)?);
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+        .map_err(|_| anyhow!("Encryption failed"))?;
+
+    Ok(EncryptedSecret {
+        salt: salt.to_vec(),
+        nonce: nonce.to_vec(),
+        ciphertext,
+    })
+}
+
+pub fn decrypt_secret(password: &str, encrypted: &EncryptedSecret) -> Result<Secret> {
+    if encrypted.nonce.len() != 24 {
+        anyhow::bail!("Invalid nonce");
+    }
+    let key = derive_key(password, &encrypted.salt)?;
+    let cipher = XChaCha20Poly1305::new(key.as_ref().into());
+    let plaintext = cipher
+        .decrypt(
+            XNonce::from_slice(&encrypted.nonce),
+            encrypted.ciphertext.as_slice(),
+        )
+        .map(Zeroizing::new)
+        .map_err(|_| anyhow!("Invalid password"))?;
+    let secret = serde_json::from_slice::<Secret>(&plaintext)?;
+    Ok(secret)
+}
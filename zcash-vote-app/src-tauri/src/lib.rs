@@ -10,13 +10,14 @@ macro_rules! tauri_export {
             let $connection = $state.pool.get()?;
             $block
         })()
-        .map_err(|e| e.to_string())
+        .map_err(zcash_vote::errors::VoteError::from)
     };
 }
 
 pub mod address;
 pub mod db;
 pub mod download;
+pub mod encrypt;
 #[path = "cash.z.wallet.sdk.rpc.rs"]
 pub mod rpc;
 pub mod state;
@@ -36,16 +37,27 @@ pub fn run() {
             state::get_election_id,
             state::save_db,
             state::open_db,
+            state::unlock,
+            state::needs_new_password,
+            state::migrate_wallet,
+            state::is_locked,
+            state::list_wallet_elections,
+            state::add_election,
+            state::select_election,
+            state::remove_election,
             address::get_address,
             db::get_prop,
             validate::validate_key,
             download::http_get,
             download::download_reference_data,
             download::sync,
+            download::watch_ballot_events,
             vote::get_sync_height,
             vote::get_available_balance,
             vote::vote,
+            vote::vote_allocation,
             vote::fetch_votes,
+            vote::refresh_votes,
             trees::compute_roots,
         ])
         .run(tauri::generate_context!())
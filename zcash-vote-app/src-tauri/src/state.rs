@@ -1,21 +1,43 @@
 use std::{fs::remove_file, sync::Mutex};
 
-use anyhow::Error;
-use orchard::keys::Scope;
+use anyhow::{Error, Result};
+use orchard::keys::{FullViewingKey, Scope};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::Connection;
 use tauri::State;
-use zcash_vote::{db::create_schema, election::Election};
+use serde::{Deserialize, Serialize};
+use zcash_vote::{
+    client::{ClientConfig, VoteClient},
+    db::{load_prop, store_prop},
+    decrypt::to_fvks,
+    election::{election_url, Election},
+    errors::VoteError,
+    organizer::verify_election,
+};
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
-use crate::db::{load_election, store_election};
+use crate::{
+    db::{
+        create_app_schema, delete_election, list_elections, load_election, load_secret,
+        load_secret_version, load_wallet, migrate_secret, store_election, store_secret,
+        store_wallet, SECRET_VERSION,
+    },
+    encrypt::Secret,
+};
 
 #[derive(Zeroize, ZeroizeOnDrop)]
 pub struct AppState {
     pub urls: Vec<String>,
+    pub id_election: u32,
     #[zeroize(skip)] pub election: Election,
     pub key: String,
+    pub passphrase: String,
+    pub accounts: Vec<u32>,
+    /// Hex encoded public key of the organizer that must sign the elections
+    #[zeroize(skip)] pub organizer_pubkey: Option<String>,
+    /// Elections of the other questions, stored when the db is saved
+    #[zeroize(skip)] pub other_elections: Vec<(Vec<String>, Election)>,
     #[zeroize(skip)] pub scope: Scope,
     #[zeroize(skip)] pub pool: r2d2::Pool<SqliteConnectionManager>,
 }
@@ -24,70 +46,171 @@ impl Default for AppState {
     fn default() -> Self {
         Self {
             urls: Default::default(),
+            id_election: 0,
             election: Default::default(),
             key: Default::default(),
+            passphrase: Default::default(),
+            accounts: vec![0],
+            organizer_pubkey: None,
+            other_elections: vec![],
             scope: Scope::External,
             pool: Pool::new(SqliteConnectionManager::memory()).unwrap(),
         }
     }
 }
 
+impl AppState {
+    pub fn fvks(&self) -> Result<Vec<(u32, FullViewingKey)>> {
+        if self.key.is_empty() {
+            anyhow::bail!("Wallet is locked");
+        }
+        to_fvks(self.election.network, &self.key, &self.accounts, &self.passphrase)
+    }
+
+    /// Client of the servers of the current election, with its id
+    pub fn client(&self) -> Result<(VoteClient, String)> {
+        Ok(VoteClient::for_election(&self.urls, ClientConfig::default())?)
+    }
+}
+
 #[tauri::command]
-pub fn save_db(path: String, state: State<Mutex<AppState>>) -> Result<(), String> {
+pub fn save_db(
+    path: String,
+    password: String,
+    state: State<Mutex<AppState>>,
+) -> Result<(), VoteError> {
     (|| {
         let mut s = state.lock().unwrap();
         {
             let _ = remove_file(&path);
             let connection = Connection::open(&path)?;
-            create_schema(&connection)?;
-            connection.execute(
-                "CREATE TABLE IF NOT EXISTS votes(
-                id_vote INTEGER PRIMARY KEY,
-                hash TEXT NOT NULL,
-                address TEXT NOT NULL,
-                amount INTEGER NOT NULL)",
-                [],
-            )?;
+            create_app_schema(&connection)?;
         }
         let manager = SqliteConnectionManager::file(&path);
         let pool = Pool::new(manager)?;
         let connection = pool.get()?;
         let urls_delim = s.urls.join(",");
-        let internal = if s.scope == Scope::Internal {
-            "true"
-        } else {
-            "false"
+        store_wallet(&connection, s.scope == Scope::Internal, &s.accounts)?;
+        for (urls, election) in s.other_elections.iter() {
+            store_election(&connection, &urls.join(","), election)?;
+        }
+        s.other_elections.clear();
+        s.id_election = store_election(&connection, &urls_delim, &s.election)?;
+        store_prop(&connection, "current_election", &s.election.id())?;
+        if let Some(organizer_pubkey) = s.organizer_pubkey.as_ref() {
+            store_prop(&connection, "organizer_pubkey", organizer_pubkey)?;
+        }
+        let secret = Secret {
+            key: s.key.clone(),
+            passphrase: s.passphrase.clone(),
         };
-        store_election(&connection, &urls_delim, &s.election, &s.key, internal)?;
+        store_secret(&connection, &password, &secret)?;
         s.pool = pool;
         Ok::<_, Error>(())
     })()
-    .map_err(|e| e.to_string())
+    .map_err(VoteError::from)
 }
 
 #[tauri::command]
-pub fn open_db(path: String, state: State<Mutex<AppState>>) -> Result<(), String> {
+pub fn open_db(path: String, state: State<Mutex<AppState>>) -> Result<(), VoteError> {
     (|| {
         let mut s = state.lock().unwrap();
         let pool = Pool::new(SqliteConnectionManager::file(path))?;
         let connection = pool.get()?;
-        let (urls, election, key, scope) = load_election(&connection)?;
+        create_app_schema(&connection)?;
+        let (scope, accounts) = load_wallet(&connection)?;
+        let id = match load_prop(&connection, "current_election")? {
+            Some(id) => id,
+            None => list_elections(&connection)?
+                .first()
+                .map(|e| e.id())
+                .ok_or(anyhow::anyhow!("No election"))?,
+        };
+        let (id_election, urls, election) = load_election(&connection, &id)?;
         s.urls = urls;
+        s.id_election = id_election;
         s.election = election;
-        s.key = key;
+        s.key.zeroize();
+        s.passphrase.zeroize();
+        s.accounts = accounts;
+        s.organizer_pubkey = load_prop(&connection, "organizer_pubkey")?;
         s.pool = pool;
         s.scope = scope;
         Ok::<_, Error>(())
     })()
-    .map_err(|e| e.to_string())
+    .map_err(VoteError::from)
+}
+
+/// Decrypt the wallet key of the opened database and keep it
+/// in the app state until the app closes
+#[tauri::command]
+pub fn unlock(password: String, state: State<Mutex<AppState>>) -> Result<(), VoteError> {
+    (|| {
+        let mut s = state.lock().unwrap();
+        let connection = s.pool.get()?;
+        let secret = load_secret(&connection, &password)?;
+        s.key = secret.key.clone();
+        s.passphrase = secret.passphrase.clone();
+        Ok::<_, Error>(())
+    })()
+    .map_err(VoteError::from)
 }
 
+/// True when the opened database keeps the wallet key in plain text,
+/// it must be encrypted with `migrate_wallet` before it can be unlocked
 #[tauri::command]
-pub fn set_election(urls: String, election: Election, key: String, internal: bool, state: State<Mutex<AppState>>) -> Result<(), String>  {
+pub fn needs_new_password(state: State<Mutex<AppState>>) -> Result<bool, VoteError> {
+    tauri_export!(state, connection, {
+        Ok::<_, Error>(load_secret_version(&connection)? < SECRET_VERSION)
+    })
+}
+
+/// Encrypt the plain text wallet key of the opened database with
+/// a new password, and unlock it
+#[tauri::command]
+pub fn migrate_wallet(password: String, state: State<Mutex<AppState>>) -> Result<(), VoteError> {
+    (|| {
+        let mut s = state.lock().unwrap();
+        let connection = s.pool.get()?;
+        migrate_secret(&connection, &password)?;
+        let secret = load_secret(&connection, &password)?;
+        s.key = secret.key.clone();
+        s.passphrase = secret.passphrase.clone();
+        Ok::<_, Error>(())
+    })()
+    .map_err(VoteError::from)
+}
+
+#[tauri::command]
+pub fn is_locked(state: State<Mutex<AppState>>) -> bool {
+    let s = state.lock().unwrap();
+    s.key.is_empty()
+}
+
+#[tauri::command]
+pub fn set_election(
+    urls: String,
+    election: Election,
+    key: String,
+    internal: bool,
+    accounts: Option<Vec<u32>>,
+    passphrase: Option<String>,
+    organizer_pubkey: Option<String>,
+    state: State<Mutex<AppState>>,
+) -> Result<(), VoteError> {
+    let organizer_pubkey = organizer_pubkey.filter(|k| !k.is_empty());
+    election.validate()?;
+    verify_election(&election, organizer_pubkey.as_deref())?;
+    let mut elections = question_elections(&urls, &election);
+    let (urls, election) = elections.remove(0);
     let mut s = state.lock().unwrap();
-    s.urls = urls.split(",").into_iter().map(String::from).collect();
+    s.organizer_pubkey = organizer_pubkey;
+    s.urls = urls;
     s.election = election;
+    s.other_elections = elections;
     s.key = key.clone();
+    s.accounts = accounts.filter(|a| !a.is_empty()).unwrap_or(vec![0]);
+    s.passphrase = passphrase.unwrap_or_default();
     if internal {
         s.scope = Scope::Internal;
     } else {
@@ -96,6 +219,24 @@ pub fn set_election(urls: String, election: Election, key: String, internal: boo
     Ok(())
 }
 
+/// Elections that ballots are cast on, one per question, with the
+/// URLs of their servers
+fn question_elections(urls: &str, election: &Election) -> Vec<(Vec<String>, Election)> {
+    let urls = urls.split(",").map(String::from).collect::<Vec<_>>();
+    if election.questions.is_empty() {
+        return vec![(urls, election.clone())];
+    }
+    election
+        .question_elections()
+        .into_iter()
+        .map(|e| {
+            let id = e.id();
+            let urls = urls.iter().map(|u| election_url(u, &id)).collect();
+            (urls, e)
+        })
+        .collect()
+}
+
 #[tauri::command]
 pub fn get_election(state: State<Mutex<AppState>>) -> Election {
     let s = state.lock().unwrap();
@@ -107,3 +248,91 @@ pub fn get_election_id(state: State<Mutex<AppState>>) -> String {
     let s = state.lock().unwrap();
     s.election.id()
 }
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ElectionSummary {
+    pub id: String,
+    pub name: String,
+    pub start_height: u32,
+    pub end_height: u32,
+    pub selected: bool,
+}
+
+#[tauri::command]
+pub fn list_wallet_elections(
+    state: State<Mutex<AppState>>,
+) -> Result<Vec<ElectionSummary>, VoteError> {
+    tauri_export!(state, connection, {
+        let current = state.election.id();
+        let elections = list_elections(&connection)?
+            .into_iter()
+            .map(|e| {
+                let id = e.id();
+                ElectionSummary {
+                    selected: id == current,
+                    id,
+                    name: e.name,
+                    start_height: e.start_height,
+                    end_height: e.end_height,
+                }
+            })
+            .collect::<Vec<_>>();
+        Ok::<_, Error>(elections)
+    })
+}
+
+/// Add an election to the opened database and switch to it
+#[tauri::command]
+pub fn add_election(
+    urls: String,
+    election: Election,
+    state: State<Mutex<AppState>>,
+) -> Result<(), VoteError> {
+    (|| {
+        election.validate()?;
+        let mut s = state.lock().unwrap();
+        verify_election(&election, s.organizer_pubkey.as_deref())?;
+        let connection = s.pool.get()?;
+        let mut elections = question_elections(&urls, &election);
+        for (urls, election) in elections.iter().skip(1) {
+            store_election(&connection, &urls.join(","), election)?;
+        }
+        let (urls, election) = elections.remove(0);
+        let id_election = store_election(&connection, &urls.join(","), &election)?;
+        store_prop(&connection, "current_election", &election.id())?;
+        s.urls = urls;
+        s.id_election = id_election;
+        s.election = election;
+        Ok::<_, Error>(())
+    })()
+    .map_err(VoteError::from)
+}
+
+#[tauri::command]
+pub fn select_election(id: String, state: State<Mutex<AppState>>) -> Result<(), VoteError> {
+    (|| {
+        let mut s = state.lock().unwrap();
+        let connection = s.pool.get()?;
+        let (id_election, urls, election) = load_election(&connection, &id)?;
+        store_prop(&connection, "current_election", &id)?;
+        s.urls = urls;
+        s.id_election = id_election;
+        s.election = election;
+        Ok::<_, Error>(())
+    })()
+    .map_err(VoteError::from)
+}
+
+#[tauri::command]
+pub fn remove_election(id: String, state: State<Mutex<AppState>>) -> Result<(), VoteError> {
+    tauri_export!(state, connection, {
+        if id == state.election.id() {
+            anyhow::bail!("Cannot remove the current election");
+        }
+        let (id_election, _, _) = load_election(&connection, &id)?;
+        let transaction = connection.unchecked_transaction()?;
+        delete_election(&transaction, id_election)?;
+        transaction.commit()?;
+        Ok::<_, Error>(())
+    })
+}
@@ -3,40 +3,47 @@ use std::sync::Mutex;
 use anyhow::{Error, Result};
 use orchard::vote::calculate_merkle_paths;
 use pasta_curves::group::ff::PrimeField as _;
-use rusqlite::Connection;
+use rusqlite::{params, Connection};
 use tauri::State;
 use zcash_vote::{
-    db::{load_prop, store_prop},
+    errors::VoteError,
     trees::{list_cmxs, list_nf_ranges},
 };
 
-use crate::state::AppState;
+use crate::{db::load_sync_height, state::AppState};
 
 #[tauri::command]
-pub fn compute_roots(state: State<Mutex<AppState>>) -> Result<(), String> {
+pub fn compute_roots(state: State<Mutex<AppState>>) -> Result<(), VoteError> {
     tauri_export!(state, connection, {
-        if load_prop(&connection, "height")?.is_some() {
-            compute_nf_root(&connection)?;
-            compute_cmx_root(&connection)?;
+        let id_election = state.id_election;
+        if load_sync_height(&connection, id_election)?.is_some() {
+            compute_nf_root(&connection, id_election)?;
+            compute_cmx_root(&connection, id_election)?;
         }
         Ok::<_, Error>(())
     })
 }
 
 // TODO: Pass positions of spent notes and return their MP
-pub fn compute_nf_root(connection: &Connection) -> Result<Vec<u8>> {
-    let nf_tree = list_nf_ranges(connection)?;
+pub fn compute_nf_root(connection: &Connection, id_election: u32) -> Result<Vec<u8>> {
+    let nf_tree = list_nf_ranges(connection, id_election)?;
     let (nf_root, _) = calculate_merkle_paths(0, &[], &nf_tree);
-    store_prop(connection, "nf_root", &hex::encode(&nf_root.to_repr()))?;
+    connection.execute(
+        "UPDATE elections SET nf_root = ?2 WHERE id_election = ?1",
+        params![id_election, hex::encode(nf_root.to_repr())],
+    )?;
 
     Ok(nf_root.to_repr().to_vec())
 }
 
 // TODO: Retrieve frontier
-pub fn compute_cmx_root(connection: &Connection) -> Result<Vec<u8>> {
-    let cmx_tree = list_cmxs(connection)?;
+pub fn compute_cmx_root(connection: &Connection, id_election: u32) -> Result<Vec<u8>> {
+    let cmx_tree = list_cmxs(connection, id_election)?;
     let (cmx_root, _) = calculate_merkle_paths(0, &[], &cmx_tree);
-    store_prop(connection, "cmx_root", &hex::encode(&cmx_root.to_repr()))?;
+    connection.execute(
+        "UPDATE elections SET cmx_root = ?2 WHERE id_election = ?1",
+        params![id_election, hex::encode(cmx_root.to_repr())],
+    )?;
 
     Ok(cmx_root.to_repr().to_vec())
 }
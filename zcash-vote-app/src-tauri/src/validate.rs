@@ -1,5 +1,5 @@
 use anyhow::{Error, Result};
-use orchard::{keys::{PreparedIncomingViewingKey, Scope}, vote::{try_decrypt_ballot, Ballot}};
+use orchard::{keys::{FullViewingKey, PreparedIncomingViewingKey, Scope}, vote::{try_decrypt_ballot, Ballot}};
 use rusqlite::Connection;
 use std::sync::Mutex;
 
@@ -7,9 +7,9 @@ use bip0039::Mnemonic;
 use tauri::State;
 use zcash_address::unified::Encoding;
 use zcash_vote::{
-    db::{load_prop, store_cmx, store_note},
-    decrypt::to_fvk,
+    db::{store_cmx, store_note},
     election::{Election, BALLOT_VK},
+    errors::VoteError,
 };
 
 use crate::{db::mark_spent, state::AppState};
@@ -26,7 +26,7 @@ pub fn validate_key(key: String) -> Result<bool, ()> {
 }
 
 #[tauri::command]
-pub fn validate_ballot(ballot: String, state: State<Mutex<AppState>>) -> Result<(), String> {
+pub fn validate_ballot(ballot: String, state: State<Mutex<AppState>>) -> Result<(), VoteError> {
     tauri_export!(state, _connection, {
         let election = &state.election;
         let ballot = serde_json::from_str::<Ballot>(&ballot)?;
@@ -37,32 +37,47 @@ pub fn validate_ballot(ballot: String, state: State<Mutex<AppState>>) -> Result<
 
 pub fn handle_ballot(
     connection: &Connection,
+    id_election: u32,
     election: &Election,
+    fvks: &[(u32, FullViewingKey)],
     height: u32,
     ballot: &Ballot,
 ) -> Result<()> {
-    let key = load_prop(connection, "key")?.ok_or(anyhow::anyhow!("no key"))?;
-    let fvk = to_fvk(&key)?;
-    let pivk = PreparedIncomingViewingKey::new(&fvk.to_ivk(Scope::External));
+    let keys = fvks
+        .iter()
+        .cloned()
+        .map(|(account, fvk)| {
+            let pivk = PreparedIncomingViewingKey::new(&fvk.to_ivk(Scope::External));
+            (account, fvk, pivk)
+        })
+        .collect::<Vec<_>>();
 
-    let position = connection.query_row("SELECT COUNT(*) FROM cmxs", [], |r| r.get::<_, u32>(0))?;
+    let position = connection.query_row(
+        "SELECT COUNT(*) FROM cmxs WHERE election = ?1",
+        [id_election],
+        |r| r.get::<_, u32>(0),
+    )?;
     let txid = ballot.data.sighash()?;
 
     for (i, action) in ballot.data.actions.iter().enumerate() {
-        mark_spent(connection, height, &action.nf)?;
-        if let Some(note) = try_decrypt_ballot(&pivk, action)? {
-            store_note(
-                connection,
-                0,
-                election.domain(),
-                &fvk,
-                height,
-                position + i as u32,
-                &txid,
-                &note,
-            )?;
+        mark_spent(connection, id_election, height, &action.nf)?;
+        for (account, fvk, pivk) in keys.iter() {
+            if let Some(note) = try_decrypt_ballot(pivk, action)? {
+                store_note(
+                    connection,
+                    id_election,
+                    *account,
+                    election.domain(),
+                    fvk,
+                    height,
+                    position + i as u32,
+                    &txid,
+                    &note,
+                )?;
+                break;
+            }
         }
-        store_cmx(connection, 0, &action.cmx)?;
+        store_cmx(connection, id_election, &action.cmx)?;
     }
     Ok(())
 }
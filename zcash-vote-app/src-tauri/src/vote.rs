@@ -1,31 +1,44 @@
-use crate::state::AppState;
+use crate::{
+    db::{load_sync_height, update_vote_status},
+    state::AppState,
+};
 use anyhow::{Error, Result};
-use reqwest::header::CONTENT_TYPE;
+use orchard::{
+    keys::{PreparedIncomingViewingKey, Scope},
+    vote::{try_decrypt_ballot, Ballot},
+};
+use pasta_curves::{group::ff::PrimeField as _, Fp};
 use serde::{Deserialize, Serialize};
-use std::sync::Mutex;
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Mutex,
+};
 use tauri::State;
 use zcash_vote::{
     address::VoteAddress,
-    db::{list_notes, load_prop},
-    decrypt::{to_fvk, to_sk},
+    as_byte256,
+    client::{BallotStatus, SubmissionStatus},
+    db::list_notes,
+    decrypt::to_sk,
     election::{BALLOT_PK, BALLOT_VK},
+    errors::{ErrorCode, ErrorResponse, VoteError},
     trees::{list_cmxs, list_nf_ranges},
 };
 
 #[tauri::command]
-pub fn get_sync_height(state: State<'_, Mutex<AppState>>) -> Result<Option<u32>, String> {
+pub fn get_sync_height(state: State<'_, Mutex<AppState>>) -> Result<Option<u32>, VoteError> {
     tauri_export!(state, connection, {
-        let height = load_prop(&connection, "height")?.map(|h| h.parse::<u32>().unwrap());
+        let height = load_sync_height(&connection, state.id_election)?;
         Ok::<_, Error>(height)
     })
 }
 
 #[tauri::command]
-pub fn get_available_balance(state: State<'_, Mutex<AppState>>) -> Result<u64, String> {
+pub fn get_available_balance(state: State<'_, Mutex<AppState>>) -> Result<u64, VoteError> {
     tauri_export!(state, connection, {
         let balance = connection.query_row(
-            "SELECT SUM(value) FROM notes WHERE spent IS NULL",
-            [],
+            "SELECT SUM(value) FROM notes WHERE spent IS NULL AND election = ?1",
+            [state.id_election],
             |r| r.get::<_, Option<u64>>(0),
         )?;
         Ok::<_, Error>(balance.unwrap_or_default())
@@ -37,98 +50,322 @@ pub async fn vote(
     address: String,
     amount: u64,
     state: State<'_, Mutex<AppState>>,
-) -> Result<String, String> {
+) -> Result<String, VoteError> {
+    vote_allocation(vec![Allocation { address, amount }], state).await
+}
+
+/// Split the voting power between several candidates
+///
+/// The ballots are built one after the other. The notes spent by a ballot
+/// are dropped and its change notes are added together with the commitments
+/// of its outputs, so that the next ballot can spend the change before the
+/// server has processed the previous ballots. They are submitted together,
+/// and the server includes all of them or none
+#[tauri::command]
+pub async fn vote_allocation(
+    allocations: Vec<Allocation>,
+    state: State<'_, Mutex<AppState>>,
+) -> Result<String, VoteError> {
     let r = async {
-        let (pool, base_urls, sk, fvk, scope, domain, signature_required) = {
+        let (pool, (client, id), id_election, network, keys, scope, domain, signature_required) = {
             let state = state.lock().unwrap();
             let pool = state.pool.clone();
-            let base_urls = state.urls.clone();
-            let sk = to_sk(&state.key)?;
-            let fvk = to_fvk(&state.key)?;
+            let client = state.client()?;
+            let network = state.election.network;
+            let keys = state
+                .fvks()?
+                .into_iter()
+                .map(|(account, fvk)| {
+                    let sk = to_sk(network, &state.key, account, &state.passphrase)?;
+                    Ok::<_, Error>((account, sk, fvk))
+                })
+                .collect::<Result<Vec<_>>>()?;
             let scope = state.scope;
             let domain = state.election.domain();
             let signature_required = state.election.signature_required;
-            (pool, base_urls, sk, fvk, scope, domain, signature_required)
+            (
+                pool,
+                client,
+                state.id_election,
+                network,
+                keys,
+                scope,
+                domain,
+                signature_required,
+            )
         };
         let mut rng = rand_core::OsRng;
-        let vaddress = VoteAddress::decode(&address)?;
         let connection = pool.get()?;
-        let notes = list_notes(&connection, 0, &fvk, scope)?;
-        let cmxs = list_cmxs(&connection)?;
-        let nfs = list_nf_ranges(&connection)?;
-        let ballot = orchard::vote::vote(
-            domain,
-            signature_required,
-            sk,
-            &fvk,
-            vaddress.0,
-            amount,
-            &notes,
-            &nfs,
-            &cmxs,
-            &mut rng,
-            &BALLOT_PK,
-            &BALLOT_VK,
-        )?;
+        let mut cmxs = list_cmxs(&connection, id_election)?;
+        let nfs = list_nf_ranges(&connection, id_election)?;
+        let mut wallets = keys
+            .into_iter()
+            .map(|(account, sk, fvk)| {
+                let notes = list_notes(&connection, id_election, account, &fvk, scope)?;
+                let pivk = PreparedIncomingViewingKey::new(&fvk.to_ivk(Scope::External));
+                Ok::<_, Error>((sk, fvk, pivk, notes))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let addresses = allocations
+            .iter()
+            .map(|a| VoteAddress::decode_for(network, &a.address))
+            .collect::<Result<Vec<_>>>()?;
+        let balances = wallets
+            .iter()
+            .map(|(_, _, _, notes)| notes.iter().map(|(n, _)| n.value().inner()).sum::<u64>())
+            .collect::<Vec<_>>();
+        let plan = plan_ballots(&allocations, &balances)?;
+
+        let mut ballots = vec![];
+        for (i, account, a) in plan {
+            let (sk, fvk, pivk, notes) = &mut wallets[account];
+            let ballot = orchard::vote::vote(
+                domain,
+                signature_required,
+                *sk,
+                fvk,
+                addresses[i].0,
+                a,
+                notes,
+                &nfs,
+                &cmxs,
+                &mut rng,
+                &BALLOT_PK,
+                &BALLOT_VK,
+            )?;
+
+            // chain the change notes into the next ballot
+            let spent = ballot
+                .data
+                .actions
+                .iter()
+                .map(|a| a.nf.clone())
+                .collect::<HashSet<_>>();
+            notes.retain(|(n, _)| {
+                !spent.contains(n.nullifier_domain(fvk, domain).to_bytes().as_slice())
+            });
+            for action in ballot.data.actions.iter() {
+                let position = cmxs.len() as u32;
+                cmxs.push(Fp::from_repr(as_byte256(&action.cmx)).unwrap());
+                if let Some(note) = try_decrypt_ballot(pivk, action)? {
+                    if note.value().inner() != 0 {
+                        notes.push((note, position));
+                    }
+                }
+            }
+            ballots.push((ballot, &allocations[i].address, a));
+        }
 
-        let client = reqwest::Client::new();
-        let mut hash = String::new();
-        let mut error = String::new();
-        let mut success = false;
-        for base_url in base_urls.iter() {
-            let url = format!("{}/ballot", base_url);
-            let rep = client
-                .post(url)
-                .header(CONTENT_TYPE, "application/json")
-                .json(&ballot)
-                .send()
-                .await?;
-            let s = rep.status().is_success();
-            let res = rep.text().await?;
-            if s {
-                success = true;
+        // the whole allocation is submitted at once, the server includes
+        // all its ballots or none of them
+        let batch = ballots.iter().map(|(b, _, _)| b.clone()).collect::<Vec<_>>();
+        let statuses = match client.post_ballots_commit(&id, &batch).await {
+            Err(VoteError::Remote(e)) if e.code == ErrorCode::BadRoot && batch.len() > 1 => {
+                return Err(VoteError::Remote(ErrorResponse {
+                    code: e.code,
+                    message: format!(
+                        "{}, the election has new ballots: synchronize and vote again",
+                        e.message
+                    ),
+                })
+                .into());
             }
-            else {
-                tracing::info!("ERROR (transient): {error}");
-                error = res;
+            r => r?,
+        };
+        let mut hashes = vec![];
+        let mut first_vote = None;
+        for ((ballot, address, amount), status) in ballots.iter().zip(statuses) {
+            let hash = &status.sighash;
+            let id_vote = crate::db::store_vote(
+                &connection,
+                id_election,
+                hash,
+                address,
+                *amount,
+                ballot,
+                first_vote,
+            )?;
+            first_vote.get_or_insert(id_vote);
+            update_vote_status(
+                &connection,
+                id_vote,
+                status.status,
+                status.height,
+                status.reason.as_deref(),
+            )?;
+            hashes.push(status.sighash);
+        }
+        let hash = hashes.join(",");
+        Ok::<_, Error>(hash)
+    };
+
+    r.await.map_err(VoteError::from)
+}
+
+/// Votes given to one candidate
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Allocation {
+    pub address: String,
+    pub amount: u64,
+}
+
+/// Ballots of an allocation, as (allocation, account, amount), in order
+///
+/// Nothing is planned unless the whole allocation is funded. A ballot
+/// spends notes of a single account, so the amount of an allocation
+/// is taken from each account in turn
+fn plan_ballots(allocations: &[Allocation], balances: &[u64]) -> Result<Vec<(usize, usize, u64)>> {
+    let total = allocations.iter().map(|a| a.amount).sum::<u64>();
+    if total > balances.iter().sum::<u64>() {
+        anyhow::bail!("Not enough funds");
+    }
+    let mut balances = balances.to_vec();
+    let mut plan = vec![];
+    for (i, allocation) in allocations.iter().enumerate() {
+        let mut remaining = allocation.amount;
+        for (account, balance) in balances.iter_mut().enumerate() {
+            if remaining == 0 {
+                break;
+            }
+            if *balance == 0 {
                 continue;
             }
+            let a = remaining.min(*balance);
+            plan.push((i, account, a));
+            *balance -= a;
+            remaining -= a;
+        }
+    }
+    Ok(plan)
+}
 
-            if hash.is_empty() {
-                hash = hex::encode(ballot.data.sighash()?);
-                crate::db::store_vote(&connection, &hash, &address, amount)?;
+/// Update the status of the pending votes from the servers
+///
+/// A ballot that no server knows about was lost before it reached
+/// a block and is broadcast again
+#[tauri::command]
+pub async fn refresh_votes(state: State<'_, Mutex<AppState>>) -> Result<(), VoteError> {
+    let r = async {
+        let (pool, (client, id), id_election) = {
+            let state = state.lock().unwrap();
+            (state.pool.clone(), state.client()?, state.id_election)
+        };
+        let pending = {
+            let connection = pool.get()?;
+            let mut s = connection.prepare(
+                "SELECT id_vote, COALESCE(batch, id_vote), hash, ballot FROM votes
+                WHERE election = ?1 AND status = 'pending' ORDER BY id_vote",
+            )?;
+            let rows = s.query_map([id_election], |r| {
+                Ok((
+                    r.get::<_, u32>(0)?,
+                    r.get::<_, u32>(1)?,
+                    r.get::<_, String>(2)?,
+                    r.get::<_, String>(3)?,
+                ))
+            })?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+
+        // ballots of the same allocation that were lost together
+        let mut lost = BTreeMap::<u32, Vec<(u32, Ballot)>>::new();
+        for (id_vote, batch, hash, ballot) in pending {
+            let status = match client.ballot_status(&id, &hash).await {
+                Ok(status) => status,
+                Err(e) => {
+                    // unknown until every server can be reached
+                    tracing::info!("Status of ballot {hash}: {e}");
+                    continue;
+                }
+            };
+            let connection = pool.get()?;
+            match status {
+                Some(BallotStatus {
+                    status: SubmissionStatus::Pending,
+                    ..
+                }) => {}
+                Some(s) => {
+                    update_vote_status(
+                        &connection,
+                        id_vote,
+                        s.status,
+                        s.height,
+                        s.reason.as_deref(),
+                    )?;
+                }
+                None => {
+                    let ballot: Ballot = serde_json::from_str(&ballot)?;
+                    lost.entry(batch).or_default().push((id_vote, ballot));
+                }
             }
         }
-        if !success {
-            anyhow::bail!(error);
+
+        for (batch, votes) in lost {
+            tracing::info!("Broadcasting the ballots of vote {batch} again");
+            let ballots = votes.iter().map(|(_, b)| b.clone()).collect::<Vec<_>>();
+            let res = client.post_ballots_commit(&id, &ballots).await;
+            let connection = pool.get()?;
+            match res {
+                Ok(statuses) => {
+                    for ((id_vote, _), s) in votes.iter().zip(statuses) {
+                        update_vote_status(
+                            &connection,
+                            *id_vote,
+                            s.status,
+                            s.height,
+                            s.reason.as_deref(),
+                        )?;
+                    }
+                }
+                Err(e) => {
+                    let reason = e.to_string();
+                    for (id_vote, _) in votes.iter() {
+                        update_vote_status(
+                            &connection,
+                            *id_vote,
+                            SubmissionStatus::Rejected,
+                            None,
+                            Some(&reason),
+                        )?;
+                    }
+                }
+            }
         }
-        Ok::<_, Error>(hash)
+        Ok::<_, Error>(())
     };
-
-    r.await.map_err(|e| e.to_string())
+    r.await.map_err(VoteError::from)
 }
 
 #[tauri::command]
-pub fn fetch_votes(state: State<'_, Mutex<AppState>>) -> Result<Vec<Vote>, String> {
+pub fn fetch_votes(state: State<'_, Mutex<AppState>>) -> Result<Vec<Vote>, VoteError> {
     tauri_export!(state, connection, {
-        let mut s = connection
-            .prepare("SELECT id_vote, hash, address, amount FROM votes ORDER BY id_vote")?;
-        let rows = s.query_map([], |r| {
+        let mut s = connection.prepare(
+            "SELECT id_vote, hash, address, amount, status, height, reason FROM votes
+            WHERE election = ?1 ORDER BY id_vote",
+        )?;
+        let rows = s.query_map([state.id_election], |r| {
             Ok((
                 r.get::<_, u32>(0)?,
                 r.get::<_, String>(1)?,
                 r.get::<_, String>(2)?,
                 r.get::<_, u64>(3)?,
+                r.get::<_, String>(4)?,
+                r.get::<_, Option<u32>>(5)?,
+                r.get::<_, Option<String>>(6)?,
             ))
         })?;
         let mut votes = vec![];
         for r in rows {
-            let (id_vote, hash, address, amount) = r?;
+            let (id_vote, hash, address, amount, status, height, reason) = r?;
             votes.push(Vote {
                 id: id_vote,
                 hash,
                 address,
                 amount,
+                status: serde_json::from_value(status.into())?,
+                height,
+                reason,
             })
         }
         Ok::<_, Error>(votes)
@@ -141,4 +378,42 @@ pub struct Vote {
     pub hash: String,
     pub address: String,
     pub amount: u64,
+    pub status: SubmissionStatus,
+    pub height: Option<u32>,
+    pub reason: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocations(amounts: &[u64]) -> Vec<Allocation> {
+        amounts
+            .iter()
+            .map(|&amount| Allocation {
+                address: String::new(),
+                amount,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn allocations_take_from_each_account_in_turn() {
+        let plan = plan_ballots(&allocations(&[30, 50]), &[40, 0, 100]).unwrap();
+        assert_eq!(plan, [(0, 0, 30), (1, 0, 10), (1, 2, 40)]);
+
+        // the whole balance can be allocated
+        let plan = plan_ballots(&allocations(&[60, 80]), &[40, 100]).unwrap();
+        assert_eq!(plan, [(0, 0, 40), (0, 1, 20), (1, 1, 80)]);
+
+        // empty allocations have no ballot
+        let plan = plan_ballots(&allocations(&[0, 10]), &[10]).unwrap();
+        assert_eq!(plan, [(1, 0, 10)]);
+    }
+
+    #[test]
+    fn unfunded_allocations_have_no_ballot() {
+        assert!(plan_ballots(&allocations(&[30, 50]), &[40, 39]).is_err());
+        assert!(plan_ballots(&allocations(&[1]), &[]).is_err());
+    }
 }
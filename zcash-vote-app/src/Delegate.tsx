@@ -66,7 +66,7 @@ export const Delegate: React.FC<ElectionProps> = ({ election }) => {
         console.log(e);
         await Swal.fire({
           icon: "error",
-          title: e,
+          title: e.message ?? e,
         });
       } finally {
         setVoting(false);
@@ -3,6 +3,7 @@ import { open, save } from "@tauri-apps/plugin-dialog";
 import { useState } from "react";
 import { SubmitHandler, useForm } from "react-hook-form";
 import { useNavigate } from "react-router-dom";
+import Swal from "sweetalert2";
 import { Button } from "@/components/ui/button";
 import { Dialog, DialogContent } from "@/components/ui/dialog";
 import { Input } from "@/components/ui/input";
@@ -29,6 +30,12 @@ const FormSchema = z.object({
       "Key must be either a 24 seed phrase or a unified viewing key with an Orchard receiver",
   }),
   internal: z.boolean().default(false),
+  accounts: z
+    .string()
+    .regex(/^\s*(\d+\s*(,\s*\d+\s*)*)?$/, "Must be a comma separated list of account numbers"),
+  passphrase: z.string(),
+  password: z.string().min(8, "Password must have at least 8 characters"),
+  organizer: z.string().regex(/^([0-9a-fA-F]{64})?$/, "Must be 64 hex characters"),
 });
 
 export function Election() {
@@ -39,6 +46,10 @@ export function Election() {
     defaultValues: {
       urls: "",
       key: "",
+      accounts: "",
+      passphrase: "",
+      password: "",
+      organizer: "",
     },
   });
 
@@ -58,12 +69,20 @@ export function Election() {
     const election: Election = JSON.parse(rep);
 
     console.log(election);
-    await invoke("set_election", {
-      urls: data.urls,
-      election: election,
-      key: data.key,
-      internal: data.internal,
-    });
+    try {
+      await invoke("set_election", {
+        urls: data.urls,
+        election: election,
+        key: data.key,
+        internal: data.internal,
+        accounts: parseAccounts(data.accounts),
+        passphrase: data.passphrase,
+        organizerPubkey: data.organizer,
+      });
+    } catch (e: any) {
+      await Swal.fire({ icon: "error", title: e.message ?? e });
+      return;
+    }
     const name = election.name;
 
     (async () => {
@@ -78,7 +97,7 @@ export function Election() {
         ],
       });
       if (dbFilename != null) {
-        await invoke("save_db", { path: dbFilename });
+        await invoke("save_db", { path: dbFilename, password: data.password });
         navigate("/overview");
       }
     })();
@@ -88,6 +107,35 @@ export function Election() {
     const dbFilename = await open();
     if (dbFilename != null) {
       await invoke("open_db", { path: dbFilename });
+      // older databases keep the wallet key in plain text
+      const upgrade: boolean = await invoke("needs_new_password");
+      const { value: password } = await Swal.fire(
+        upgrade
+          ? {
+              title: "Choose a password to encrypt the wallet",
+              text: "This election file was saved by an older version of the app",
+              input: "password",
+              showCancelButton: true,
+              inputValidator: (value) =>
+                value.length < 8
+                  ? "Password must have at least 8 characters"
+                  : null,
+            }
+          : {
+              title: "Enter the wallet password",
+              input: "password",
+              showCancelButton: true,
+            }
+      );
+      if (password == null) return;
+      try {
+        await invoke(upgrade ? "migrate_wallet" : "unlock", {
+          password: password,
+        });
+      } catch (e: any) {
+        await Swal.fire({ icon: "error", title: e.message ?? e });
+        return;
+      }
       navigate("/overview");
     }
   };
@@ -149,6 +197,58 @@ export function Election() {
                     </FormItem>
                   )}
                 />
+                <FormField
+                  control={form.control}
+                  name="passphrase"
+                  render={({ field }) => (
+                    <FormItem className="flex flex-row items-center justify-between rounded-lg border p-3 shadow-sm">
+                      <FormLabel>Seed Passphrase</FormLabel>
+                      <FormControl>
+                        <Input type="password" placeholder="Optional" {...field} />
+                      </FormControl>
+                      <FormMessage />
+                    </FormItem>
+                  )}
+                />
+                <FormField
+                  control={form.control}
+                  name="accounts"
+                  render={({ field }) => (
+                    <FormItem className="flex flex-row items-center justify-between rounded-lg border p-3 shadow-sm">
+                      <FormLabel>Accounts</FormLabel>
+                      <FormControl>
+                        <Input placeholder="0" {...field} />
+                      </FormControl>
+                      <FormMessage />
+                    </FormItem>
+                  )}
+                />
+                <FormField
+                  control={form.control}
+                  name="password"
+                  render={({ field }) => (
+                    <FormItem className="flex flex-row items-center justify-between rounded-lg border p-3 shadow-sm">
+                      <FormLabel>Wallet Password</FormLabel>
+                      <FormControl>
+                        <Input type="password" {...field} />
+                      </FormControl>
+                      <FormMessage />
+                    </FormItem>
+                  )}
+                />
+                <FormField
+                  control={form.control}
+                  name="organizer"
+                  render={({ field }) => (
+                    <FormItem className="flex flex-row items-center justify-between rounded-lg border p-3 shadow-sm">
+                      <FormLabel>Organizer Key</FormLabel>
+                      <FormControl>
+                        <Input placeholder="Optional" {...field} />
+                      </FormControl>
+                      <FormMessage />
+                    </FormItem>
+                  )}
+                />
                 <FormField
                   control={form.control}
                   name="internal"
@@ -189,6 +289,15 @@ async function validateURLs(urlsDelimited: string) {
   return true;
 }
 
+// account numbers of the seed, empty for the first account
+function parseAccounts(accounts: string): number[] {
+  return accounts
+    .split(",")
+    .map((a) => a.trim())
+    .filter((a) => a != "")
+    .map(Number);
+}
+
 async function validateKey(key: string) {
   const isValid: boolean = await invoke("validate_key", { key: key });
   return isValid;
@@ -1,5 +1,6 @@
 import { invoke } from "@tauri-apps/api/core"
 import { useEffect, useState } from "react"
+import { Button } from "@/components/ui/button";
 import { Card } from "@/components/ui/card";
 import { Table, TableBody, TableCell, TableHead, TableHeader, TableRow } from "./components/ui/table";
 
@@ -8,31 +9,41 @@ type Vote = {
     hash: string;
     address: string;
     amount: number;
+    status: "pending" | "included" | "rejected";
+    height: number | null;
+    reason: string | null;
     choice: string | undefined;
 }
 
 export const History: React.FC<ElectionProps> = ({election}) => {
     const [votes, setVotes] = useState<Vote[] | undefined>()
-    useEffect(() => {
-        if (election == undefined) return
 
-        (
-            async () => {
-                console.log(election)
-                const votes: Vote[] = await invoke('fetch_votes')
-                for (const v of votes) {
-                    const c = election.candidates.find((c) => c.address == v.address)
-                    if (c) {
-                        v.choice = c.choice
-                    }
-                }
-                setVotes(votes)
+    const refresh = async () => {
+        try {
+            await invoke('refresh_votes')
+        } catch (e) {
+            console.log(e)
+        }
+        const votes: Vote[] = await invoke('fetch_votes')
+        for (const v of votes) {
+            const c = election.candidates.find((c) => c.address == v.address)
+            if (c) {
+                v.choice = c.choice
             }
-        )()
+        }
+        setVotes(votes)
+    }
+
+    useEffect(() => {
+        if (election == undefined) return
+        refresh()
     }, [election])
 
     return <div className="flex justify-center items-center h-fill bg-gray-100">
         <Card className="w-full max-w-full">
+            <div className="flex justify-end p-2">
+                <Button onClick={refresh}>Refresh</Button>
+            </div>
             <Table>
                 <TableHeader>
                     <TableRow>
@@ -40,6 +51,7 @@ export const History: React.FC<ElectionProps> = ({election}) => {
                         <TableHead>Address</TableHead>
                         <TableHead>Amount</TableHead>
                         <TableHead>Choice</TableHead>
+                        <TableHead>Status</TableHead>
                     </TableRow>
                 </TableHeader>
                 <TableBody>
@@ -49,6 +61,10 @@ export const History: React.FC<ElectionProps> = ({election}) => {
                             <TableCell className="max-w-md break-all">{v.address}</TableCell>
                             <TableCell>{v.amount / 100000}</TableCell>
                             <TableCell>{v.choice}</TableCell>
+                            <TableCell title={v.reason ?? undefined}>
+                                {v.status == "included" ? `included at ${v.height ?? "?"}` : v.status}
+                                {v.status == "rejected" && <div className="text-red-600 break-all">{v.reason}</div>}
+                            </TableCell>
                         </TableRow>
                     })}
                 </TableBody>
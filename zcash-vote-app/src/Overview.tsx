@@ -21,6 +21,9 @@ import { Alert, AlertDescription, AlertTitle } from "./components/ui/alert";
 import Swal from "sweetalert2";
 import { Spinner } from "./Spinner";
 
+// election whose ballot events are forwarded by the backend
+let watched: string | undefined;
+
 export const Overview: React.FC<ElectionProps> = ({ election }) => {
   const [height, setHeight] = useState<number | null | undefined>();
   const [balance, setBalance] = useState<number | undefined>();
@@ -47,6 +50,24 @@ export const Overview: React.FC<ElectionProps> = ({ election }) => {
         election: election,
       });
       setId(id);
+
+      if (watched != id) {
+        watched = id;
+        const channel = new Channel<BallotEvent | null>();
+        channel.onmessage = async () => {
+          // new ballots may have spent or given us notes
+          try {
+            await invoke("sync");
+            const balance: number = await invoke("get_available_balance", {});
+            setBalance(balance / 100000);
+          } catch {}
+        };
+        invoke("watch_ballot_events", { channel: channel })
+          .catch(console.log)
+          .finally(() => {
+            if (watched == id) watched = undefined;
+          });
+      }
     })();
   }, []);
 
@@ -65,7 +86,7 @@ export const Overview: React.FC<ElectionProps> = ({ election }) => {
         await invoke("reset");
         await Swal.fire({
           icon: "error",
-          title: e,
+          title: e.message ?? e,
         });
       }
     })();
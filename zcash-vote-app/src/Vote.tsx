@@ -21,6 +21,8 @@ import {
 import { Button } from "./components/ui/button";
 import { Input } from "./components/ui/input";
 import { RadioGroup, RadioGroupItem } from "./components/ui/radio-group";
+import { Switch } from "./components/ui/switch";
+import { Label } from "./components/ui/label";
 import { z } from "zod";
 import { zodResolver } from "@hookform/resolvers/zod";
 import { useNavigate } from "react-router-dom";
@@ -33,6 +35,8 @@ const voteSchema = z.object({
 export const Vote: React.FC<ElectionProps> = ({ election }) => {
   const navigate = useNavigate();
   const [busy, setBusy] = useState(false);
+  const [split, setSplit] = useState(false);
+  const [amounts, setAmounts] = useState<Record<string, string>>({});
 
   const form = useForm<z.infer<typeof voteSchema>>({
     resolver: zodResolver(voteSchema),
@@ -59,7 +63,37 @@ export const Vote: React.FC<ElectionProps> = ({ election }) => {
         console.log(e);
         await Swal.fire({
           icon: "error",
-          title: e,
+          title: e.message ?? e,
+        });
+      } finally {
+        setBusy(false);
+      }
+    })();
+  };
+
+  // All the allocations are built and submitted in one operation
+  const onSubmitAllocation = () => {
+    const allocations: Vote[] = election.candidates
+      .map((c) => ({
+        address: c.address,
+        amount: Math.floor(Number(amounts[c.address] ?? 0) * 100000),
+      }))
+      .filter((a) => a.amount > 0);
+    if (allocations.length == 0) return;
+    setBusy(true);
+    (async () => {
+      try {
+        const hash: string = await invoke("vote_allocation", { allocations });
+        await Swal.fire({
+          icon: "success",
+          title: hash,
+        });
+        navigate("/overview");
+      } catch (e: any) {
+        console.log(e);
+        await Swal.fire({
+          icon: "error",
+          title: e.message ?? e,
         });
       } finally {
         setBusy(false);
@@ -76,7 +110,30 @@ export const Vote: React.FC<ElectionProps> = ({ election }) => {
           <CardTitle>Vote</CardTitle>
           <CardDescription>{election.question}</CardDescription>
         </CardHeader>
-        <Form {...form}>
+        <div className="flex items-center gap-2 p-2">
+          <Switch id="split" checked={split} onCheckedChange={setSplit} />
+          <Label htmlFor="split">Split my votes between several choices</Label>
+        </div>
+        {split && (
+          <div className="flex flex-col gap-4 bg-gray-100 p-2">
+            {election.candidates.map((c) => (
+              <div key={c.address} className="flex items-center justify-between gap-4">
+                <Label htmlFor={`split-${c.address}`}>{c.choice}</Label>
+                <Input
+                  id={`split-${c.address}`}
+                  type="number"
+                  className="w-40"
+                  value={amounts[c.address] ?? ""}
+                  onChange={(v) =>
+                    setAmounts({ ...amounts, [c.address]: v.target.value })
+                  }
+                />
+              </div>
+            ))}
+            <Button onClick={onSubmitAllocation}>Vote</Button>
+          </div>
+        )}
+        {!split && <Form {...form}>
           <form className="flex bg-gray-100" onSubmit={handleSubmit(onSubmit)}>
             <div className="flex flex-col gap-4">
               <FormField
@@ -133,7 +190,7 @@ export const Vote: React.FC<ElectionProps> = ({ election }) => {
               <Button type="submit">Vote</Button>
             </div>
           </form>
-        </Form>
+        </Form>}
       </Card>
       {busy && (
         <div className="fixed inset-0 bg-black/50 flex items-center justify-center z-50">
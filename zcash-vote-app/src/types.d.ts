@@ -11,6 +11,7 @@ type Frontier = {
 
 type Election = {
     id: string;
+    version?: number;
     name: string;
     start_height: number;
     end_height: number;
@@ -20,6 +21,29 @@ type Election = {
     cmx: string;
     nf: string;
     cmx_frontier: Frontier;
+    network?: "main" | "test" | "regtest";
+    signature?: ElectionSignature;
+    questions?: Question[];
+    parent?: { id: string; index: number };
+    snapshot?: "range" | "full_balance";
+}
+
+type Question = {
+    question: string;
+    candidates: CandidateChoice[];
+}
+
+type ElectionSignature = {
+    organizer_pubkey: string;
+    signature: string;
+}
+
+type BallotEvent = {
+    election: string;
+    height: number;
+    sighash: string;
+    cmx_root: string;
+    dnfs: string[];
 }
 
 type Vote = {
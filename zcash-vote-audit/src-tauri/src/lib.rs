@@ -2,13 +2,18 @@ use std::collections::BTreeSet;
 
 use anyhow::Error;
 use bip0039::Mnemonic;
-use orchard::{keys::{FullViewingKey, PreparedIncomingViewingKey, Scope, SpendingKey}, vote::{try_decrypt_ballot, validate_ballot, Ballot, BallotData, OrchardHash}};
+use orchard::{keys::{FullViewingKey, PreparedIncomingViewingKey, SpendingKey}, vote::{try_decrypt_ballot, validate_ballot, BallotData, OrchardHash}};
 use pasta_curves::{group::ff::PrimeField, Fp};
 use serde::{Deserialize, Serialize};
 use zcash_vote::{
     address::VoteAddress,
     as_byte256,
+    client::{ClientConfig, VoteClient},
     election::{Election, BALLOT_VK},
+    decrypt::to_candidate_fvk,
+    errors::VoteError,
+    organizer::verify_election,
+    shares::combine_seed,
 };
 
 #[derive(Clone, Debug)]
@@ -20,100 +25,162 @@ pub struct CountResult {
     amount: u64,
 }
 
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct QuestionResult {
+    question: String,
+    counts: Vec<CountResult>,
+}
+
 #[tauri::command]
-async fn audit(url: String, seed: String) -> Result<Vec<CountResult>, String> {
+async fn audit(
+    url: String,
+    seed: String,
+    organizer_pubkey: Option<String>,
+    viewing_keys: Option<Vec<String>>,
+    shares: Option<Vec<String>>,
+) -> Result<Vec<QuestionResult>, VoteError> {
     let res = async {
-        let election: Election = reqwest::get(&url).await?.json().await?;
-        let mnemonic = Mnemonic::from_phrase(&seed)?;
-        let seed = mnemonic.to_seed("vote");
-        let mut counts = vec![];
-        for (i, c) in election.candidates.iter().enumerate() {
-            let sk = SpendingKey::from_zip32_seed(&seed, 133, i as u32).unwrap();
-            let fvk = FullViewingKey::from(&sk);
-            let address = fvk.address_at(0u64, Scope::External);
-            let vote_address = VoteAddress::decode(&c.address)?;
-            if vote_address.0 != address {
-                anyhow::bail!("Invalid address for choice #{i}");
-            }
-            let ivk = fvk.to_ivk(Scope::External);
-            let pivk = PreparedIncomingViewingKey::new(&ivk);
-            counts.push(Count(pivk, fvk, 0u64));
-        }
-
-        let mut candidate_nfs = vec![];
-        let mut frontier = election.cmx_frontier.clone().unwrap();
-        let mut cmx_roots = BTreeSet::<Fp>::new();
-        cmx_roots.insert(Fp::from_repr(election.cmx.0).unwrap());
-        let mut nfs = BTreeSet::<Fp>::new();
-        let n = reqwest::get(&format!("{url}/num_ballots"))
-            .await?
-            .text()
-            .await?;
-        let n = n.parse::<u32>()?;
-        for i in 1..=n {
-            let ballot: Ballot = reqwest::get(&format!("{url}/ballot/height/{i}"))
-                .await?
-                .json()
-                .await?;
-            let BallotData {
-                version,
-                domain,
-                actions,
-                anchors,
-            } = ballot.data.clone();
-            if version != 1 {
-                anyhow::bail!("Invalid version");
-            }
-            let domain = Fp::from_repr(as_byte256(&domain)).unwrap();
-            if domain != election.domain() {}
-            let nf = &anchors.nf;
-            if nf != &election.nf.0 {
-                anyhow::bail!("nf roots do not match");
-            }
-            let cmx = Fp::from_repr(as_byte256(&anchors.cmx)).unwrap();
-            if !cmx_roots.contains(&cmx) {
-                anyhow::bail!("cmx roots do not match");
-            }
+        let (client, id) = VoteClient::for_election(&[url], ClientConfig::default())?;
+        let election = client.election(&id).await?;
+        election.validate()?;
+        verify_election(&election, organizer_pubkey.as_deref().filter(|k| !k.is_empty()))?;
+        // Candidates may supply their own viewing key, the others
+        // are derived from the election seed
+        let viewing_keys = viewing_keys.unwrap_or_default();
+        // The seed is given directly or reconstructed from the trustee shares
+        let shares = shares.unwrap_or_default();
+        let seed = if shares.iter().any(|s| !s.trim().is_empty()) {
+            combine_seed(&shares)?
+        } else {
+            seed
+        };
+        let seed = if seed.trim().is_empty() {
+            None
+        } else {
+            Some(Mnemonic::from_phrase(&seed)?.to_seed("vote"))
+        };
 
-            for action in actions.iter() {
-                let nf = Fp::from_repr(as_byte256(&action.nf)).unwrap();
-                if nfs.contains(&nf) {
-                    anyhow::bail!("duplicate dnf");
-                }
-                nfs.insert(nf);
-                frontier.append(OrchardHash(as_byte256(&action.cmx)));
-                for c in counts.iter_mut() {
-                    if let Some(note) = try_decrypt_ballot(&c.0, action)? {
-                        let candidate_nf = note.nullifier_domain(&c.1, domain);
-                        candidate_nfs.push(Fp::from_repr(candidate_nf.to_bytes()).unwrap());
-                        c.2 += note.value().inner();
+        // Every question is a separate election on the server,
+        // candidates are numbered across the whole election
+        let mut results = vec![];
+        let mut i = 0usize;
+        for e in election.question_elections() {
+            let mut counts = vec![];
+            for c in e.candidates.iter() {
+                let fvk = match viewing_keys.get(i).filter(|k| !k.trim().is_empty()) {
+                    Some(key) => to_candidate_fvk(e.network, key)?,
+                    None => {
+                        let seed = seed
+                            .as_ref()
+                            .ok_or(anyhow::anyhow!("Missing viewing key for choice #{i}"))?;
+                        let sk =
+                            SpendingKey::from_zip32_seed(seed, e.network.coin_type(), i as u32)
+                                .unwrap();
+                        FullViewingKey::from(&sk)
                     }
-                }
+                };
+                let vote_address = VoteAddress::decode_for(e.network, &c.address)?;
+                let scope = fvk
+                    .scope_for_address(&vote_address.0)
+                    .ok_or(anyhow::anyhow!("Invalid address for choice #{i}"))?;
+                let ivk = fvk.to_ivk(scope);
+                let pivk = PreparedIncomingViewingKey::new(&ivk);
+                counts.push(Count(pivk, fvk, 0u64));
+                i += 1;
             }
-            cmx_roots.insert(Fp::from_repr(frontier.root()).unwrap());
+            let counts = tally(&client, &e, counts).await?;
+            results.push(QuestionResult {
+                question: e.question.clone(),
+                counts,
+            });
+        }
+        Ok::<_, Error>(results)
+    };
+
+    res.await.map_err(VoteError::from)
+}
 
-            validate_ballot(ballot, election.signature_required, &BALLOT_VK)?;
+/// Verify the ballots of a question and add up the votes of every candidate
+async fn tally(
+    client: &VoteClient,
+    election: &Election,
+    mut counts: Vec<Count>,
+) -> Result<Vec<CountResult>, Error> {
+    let mut candidate_nfs = vec![];
+    // validated to be present
+    let mut frontier = election.cmx_frontier.clone().unwrap();
+    let mut cmx_roots = BTreeSet::<Fp>::new();
+    cmx_roots.insert(Fp::from_repr(election.cmx.0).unwrap());
+    let mut nfs = BTreeSet::<Fp>::new();
+    let id = election.id();
+    let n = client.num_ballots(&id).await?;
+    let mut ballots = vec![];
+    while (ballots.len() as u32) < n {
+        let page = client.ballot_range(&id, ballots.len() as u32 + 1, n).await?;
+        if page.is_empty() {
+            anyhow::bail!("Missing ballot at height {}", ballots.len() + 1);
+        }
+        ballots.extend(page.into_iter().map(|(_, ballot)| ballot));
+    }
+    for ballot in ballots {
+        let BallotData {
+            version,
+            domain,
+            actions,
+            anchors,
+        } = ballot.data.clone();
+        if version != 1 {
+            anyhow::bail!("Invalid version");
+        }
+        let domain = Fp::from_repr(as_byte256(&domain)).unwrap();
+        if domain != election.domain() {
+            anyhow::bail!("Ballot is for another election");
+        }
+        let nf = &anchors.nf;
+        if nf != &election.nf.0 {
+            return Err(VoteError::BadRoot("nullifier".to_string()).into());
+        }
+        let cmx = Fp::from_repr(as_byte256(&anchors.cmx)).unwrap();
+        if !cmx_roots.contains(&cmx) {
+            return Err(VoteError::BadRoot("note commitment".to_string()).into());
         }
 
-        // Check that candidate notes are unspent
-        for dnf in candidate_nfs.iter() {
-            if nfs.contains(dnf) {
-                anyhow::bail!("candidate notes cannot be spent");
+        for action in actions.iter() {
+            let nf = Fp::from_repr(as_byte256(&action.nf)).unwrap();
+            if nfs.contains(&nf) {
+                return Err(VoteError::DoubleNullifier(hex::encode(&action.nf)).into());
+            }
+            nfs.insert(nf);
+            frontier.append(OrchardHash(as_byte256(&action.cmx)));
+            for c in counts.iter_mut() {
+                if let Some(note) = try_decrypt_ballot(&c.0, action)? {
+                    let candidate_nf = note.nullifier_domain(&c.1, domain);
+                    candidate_nfs.push(Fp::from_repr(candidate_nf.to_bytes()).unwrap());
+                    c.2 += note.value().inner();
+                }
             }
         }
+        cmx_roots.insert(Fp::from_repr(frontier.root()).unwrap());
 
-        let res = counts
-            .iter()
-            .zip(election.candidates.iter())
-            .map(|(c, cc)| CountResult {
-                choice: cc.choice.clone(),
-                amount: c.2,
-            })
-            .collect::<Vec<_>>();
-        Ok::<_, Error>(res)
-    };
+        validate_ballot(ballot, election.signature_required, &BALLOT_VK)?;
+    }
+
+    // Check that candidate notes are unspent
+    for dnf in candidate_nfs.iter() {
+        if nfs.contains(dnf) {
+            anyhow::bail!("candidate notes cannot be spent");
+        }
+    }
 
-    res.await.map_err(|e| e.to_string())
+    let res = counts
+        .iter()
+        .zip(election.candidates.iter())
+        .map(|(c, cc)| CountResult {
+            choice: cc.choice.clone(),
+            amount: c.2,
+        })
+        .collect::<Vec<_>>();
+    Ok(res)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
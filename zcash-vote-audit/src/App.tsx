@@ -15,10 +15,14 @@ import { z } from "zod";
 import { zodResolver } from "@hookform/resolvers/zod";
 import { Input } from "./components/ui/input";
 import { Button } from "./components/ui/button";
+import { Textarea } from "./components/ui/textarea";
 
 const auditSchema = z.object({
   url: z.string(),
   seed: z.string(),
+  organizerPubkey: z.string(),
+  viewingKeys: z.string(),
+  shares: z.string(),
 });
 
 type Count = {
@@ -26,14 +30,22 @@ type Count = {
   amount: number;
 };
 
+type QuestionResult = {
+  question: string;
+  counts: Count[];
+};
+
 function App() {
   const [validating, setValidating] = useState(false);
-  const [results, setResults] = useState<Count[]>([]);
+  const [results, setResults] = useState<QuestionResult[]>([]);
   const form = useForm<z.infer<typeof auditSchema>>({
     resolver: zodResolver(auditSchema),
     defaultValues: {
       url: "",
       seed: "",
+      organizerPubkey: "",
+      viewingKeys: "",
+      shares: "",
     },
   });
   const { control, handleSubmit } = form;
@@ -44,13 +56,16 @@ function App() {
     (async () => {
       try {
         setValidating(true);
-        const res: Count[] = await invoke("audit", params);
+        // one line per candidate, empty lines use the election seed
+        const viewingKeys = params.viewingKeys.split("\n").map((k) => k.trim());
+        const shares = params.shares.split("\n").map((s) => s.trim());
+        const res: QuestionResult[] = await invoke("audit", { ...params, viewingKeys, shares });
         console.log(res);
         setResults(res);
       } catch (e: any) {
         await Swal.fire({
           icon: "error",
-          title: e,
+          title: e.message ?? e,
         });
       } finally {
         setValidating(false);
@@ -58,9 +73,6 @@ function App() {
     })();
   };
 
-  const labels = results.map((c) => c.choice);
-  const votes = results.map((c) => c.amount / 100000);
-
   return (
     <main>
       {validating && (
@@ -98,6 +110,36 @@ function App() {
                     </FormItem>
                   )}
                 />
+                <FormField
+                  name="shares"
+                  control={control}
+                  render={({ field }) => (
+                    <FormItem>
+                      <FormLabel>Trustee Shares (one per line, instead of the seed)</FormLabel>
+                      <Textarea {...field} rows={4} />
+                    </FormItem>
+                  )}
+                />
+                <FormField
+                  name="organizerPubkey"
+                  control={control}
+                  render={({ field }) => (
+                    <FormItem>
+                      <FormLabel>Organizer Key (optional)</FormLabel>
+                      <Input {...field} />
+                    </FormItem>
+                  )}
+                />
+                <FormField
+                  name="viewingKeys"
+                  control={control}
+                  render={({ field }) => (
+                    <FormItem>
+                      <FormLabel>Candidate Viewing Keys (one per line, optional)</FormLabel>
+                      <Textarea {...field} rows={4} />
+                    </FormItem>
+                  )}
+                />
               </CardContent>
               <CardFooter>
                 <Button type="submit">Verify Ballots and Show Results</Button>
@@ -106,35 +148,40 @@ function App() {
           </form>
         </Form>
       )}
-      <EChart
-        renderer={"svg"}
-        onClick={() => console.log("clicked!")}
-        style={{
-          height: "600px",
-          width: "100%",
-        }}
-        xAxis={{
-          type: "category",
-          data: labels,
-        }}
-        yAxis={{
-          type: "value",
-        }}
-        series={[
-          {
-            data: votes,
-            type: "bar",
-            showBackground: true,
-            backgroundStyle: {
-              color: "rgba(180, 180, 180, 0.2)",
-            },
-            label: {
-              show: true,
-              position: "inside",
-            },
-          },
-        ]}
-      />
+      {results.map((q, i) => (
+        <div key={i}>
+          <h2 className="text-xl font-bold text-center pt-4">{q.question}</h2>
+          <EChart
+            renderer={"svg"}
+            onClick={() => console.log("clicked!")}
+            style={{
+              height: "600px",
+              width: "100%",
+            }}
+            xAxis={{
+              type: "category",
+              data: q.counts.map((c) => c.choice),
+            }}
+            yAxis={{
+              type: "value",
+            }}
+            series={[
+              {
+                data: q.counts.map((c) => c.amount / 100000),
+                type: "bar",
+                showBackground: true,
+                backgroundStyle: {
+                  color: "rgba(180, 180, 180, 0.2)",
+                },
+                label: {
+                  show: true,
+                  position: "inside",
+                },
+              },
+            ]}
+          />
+        </div>
+      ))}
     </main>
   );
 }
@@ -11,7 +11,11 @@ use zcash_vote::{
     address::VoteAddress,
     db::create_schema,
     download::download_reference_data,
-    election::{CandidateChoice, Election},
+    election::{CandidateChoice, Election, Question, SnapshotMode, ELECTION_VERSION},
+    errors::VoteError,
+    network::Network,
+    organizer::{sign_election, OrganizerKey},
+    shares::split_seed,
     trees::{compute_cmx_root, compute_nf_root},
 };
 
@@ -23,11 +27,35 @@ pub struct ElectionTemplate {
     question: String,
     choices: String,
     signature_required: bool,
+    #[serde(default)]
+    network: Network,
+    /// Hex encoded Ed25519 secret key of the organizer
+    #[serde(default)]
+    organizer_key: Option<String>,
+    /// Split the tally seed into `threshold` of `trustees` shares
+    #[serde(default)]
+    threshold: Option<u8>,
+    #[serde(default)]
+    trustees: Option<u8>,
+    /// Questions after the first one
+    #[serde(default)]
+    questions: Vec<QuestionTemplate>,
+    #[serde(default)]
+    snapshot: SnapshotMode,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct QuestionTemplate {
+    question: String,
+    choices: String,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ElectionData {
     pub seed: String,
+    /// Shares of the seed, when it is held by trustees
+    #[serde(default)]
+    pub shares: Vec<String>,
     pub election: Election,
 }
 
@@ -35,73 +63,130 @@ pub struct ElectionData {
 async fn create_election(
     election: ElectionTemplate,
     channel: Channel<u32>,
-) -> Result<String, String> {
+) -> Result<String, VoteError> {
     let e = async {
         let mnemonic = Mnemonic::generate(bip0039::Count::Words24);
         let phrase = mnemonic.phrase().to_string();
         let seed = mnemonic.to_seed("vote");
-        let candidates = election
-            .choices
-            .trim()
-            .split("\n")
-            .enumerate()
-            .map(|(i, choice)| {
-                let spk = SpendingKey::from_zip32_seed(&seed, 133, i as u32).unwrap();
-                let fvk = FullViewingKey::from(&spk);
-                let address = fvk.address_at(0u64, Scope::External);
-                let vote_address = VoteAddress(address);
-
-                CandidateChoice {
+        let network = election.network;
+        // A choice line may end with "| <address>" when the candidate
+        // brings its own address. Otherwise, it is derived from the seed
+        // with the index of the candidate in the whole election
+        let mut derived = false;
+        let mut index = 0u32;
+        let mut questions = vec![];
+        let templates = std::iter::once(QuestionTemplate {
+            question: election.question.clone(),
+            choices: election.choices.clone(),
+        })
+        .chain(election.questions.iter().cloned());
+        for (q, template) in templates.enumerate() {
+            let mut candidates = vec![];
+            for line in template.choices.trim().split("\n") {
+                let i = index;
+                index += 1;
+                let (choice, vote_address) = match line.rsplit_once('|') {
+                    Some((choice, address)) => {
+                        let vote_address = VoteAddress::decode_for(network, address.trim())
+                            .map_err(|e| anyhow::anyhow!("Question #{q}, choice #{i}: {e}"))?;
+                        (choice.trim(), vote_address)
+                    }
+                    None => {
+                        derived = true;
+                        let spk = SpendingKey::from_zip32_seed(&seed, network.coin_type(), i)
+                            .unwrap();
+                        let fvk = FullViewingKey::from(&spk);
+                        let address = fvk.address_at(0u64, Scope::External);
+                        (line.trim(), VoteAddress(address, network))
+                    }
+                };
+
+                candidates.push(CandidateChoice {
                     address: vote_address.to_string(),
                     choice: choice.to_string(),
-                }
-            })
-            .collect::<Vec<_>>();
+                });
+            }
+            questions.push(Question {
+                question: template.question,
+                candidates,
+            });
+        }
+        // Single question elections keep the v2 layout
+        let (question, candidates, questions) = if questions.len() == 1 {
+            let q = questions.pop().unwrap();
+            (q.question, q.candidates, vec![])
+        } else {
+            (String::new(), vec![], questions)
+        };
+        // Nothing to keep secret when every candidate has its own address
+        let phrase = if derived { phrase } else { String::new() };
 
         let manager = SqliteConnectionManager::memory();
         let pool = Pool::new(manager)?;
 
-        let start = election.start;
+        // full balance snapshots scan every Orchard block
+        let start = match election.snapshot {
+            SnapshotMode::Range => election.start,
+            SnapshotMode::FullBalance => network.orchard_activation_height() - 1,
+        };
         let end = election.end;
 
         let mut e = Election {
+            version: ELECTION_VERSION,
             name: election.name,
             start_height: start,
             end_height: end,
-            question: election.question,
+            question,
             candidates,
             signature_required: election.signature_required,
             cmx: Default::default(),
             nf: Default::default(),
             cmx_frontier: Default::default(),
+            network,
+            signature: None,
+            questions,
+            parent: None,
+            snapshot: election.snapshot,
         };
 
         let connection = pool.get()?;
         create_schema(&connection)?;
 
         connection.execute("BEGIN TRANSACTION", [])?;
-        let lwd_url = std::env::var("LWD_URL").unwrap_or("https://zec.rocks".to_string());
+        let lwd_url = network.lwd_url();
         let ch = channel.clone();
         let (connection, _) =
-            download_reference_data(connection, 0, &e, None, orchard::keys::Scope::External,
+            download_reference_data(connection, 0, &e, &[], orchard::keys::Scope::External,
                 &lwd_url, move |h| {
                 let p = (100 * (h - start)) / (end - start) / 2;
                 let _ = ch.send(p);
             })
             .await?;
 
-        let nf_root = compute_nf_root(&connection)?;
+        let nf_root = compute_nf_root(&connection, 0)?;
         channel.send(75)?;
-        let (cmx_root, frontier) = compute_cmx_root(&connection)?;
+        let (cmx_root, frontier) = compute_cmx_root(&connection, 0)?;
         channel.send(100)?;
         connection.execute("COMMIT", [])?;
 
         e.nf = nf_root;
         e.cmx = cmx_root;
         e.cmx_frontier = frontier;
+        if let Some(organizer_key) = election.organizer_key.as_deref().filter(|k| !k.is_empty()) {
+            sign_election(&mut e, organizer_key)?;
+        }
+
+        // Only the trustee shares are returned, never the whole seed
+        let (phrase, shares) = match (election.threshold, election.trustees) {
+            (Some(threshold), Some(trustees)) if !phrase.is_empty() => {
+                (String::new(), split_seed(&phrase, threshold, trustees)?)
+            }
+            _ => (phrase, vec![]),
+        };
 
         let e = ElectionData {
             seed: phrase,
+            shares,
             election: e,
         };
 
@@ -109,17 +194,22 @@ async fn create_election(
 
         Ok::<_, Error>(e)
     };
-    e.await.map_err(|e| e.to_string())
+    e.await.map_err(VoteError::from)
 }
 
 #[tauri::command]
-fn save_election(path: String, election: Election) -> Result<(), String> {
+fn save_election(path: String, election: Election) -> Result<(), VoteError> {
     let r = || {
         let mut f = File::create(path)?;
         serde_json::to_writer(&mut f, &election)?;
         Ok::<_, Error>(())
     };
-    r().map_err(|e| e.to_string())
+    r().map_err(VoteError::from)
+}
+
+#[tauri::command]
+fn new_organizer_key() -> OrganizerKey {
+    OrganizerKey::generate()
 }
 
 #[tauri::command]
@@ -132,7 +222,12 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
-        .invoke_handler(tauri::generate_handler![create_election, get_election_id, save_election])
+        .invoke_handler(tauri::generate_handler![
+            create_election,
+            get_election_id,
+            save_election,
+            new_organizer_key
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -1,4 +1,4 @@
-import { SubmitHandler, useForm } from "react-hook-form";
+import { SubmitHandler, useFieldArray, useForm } from "react-hook-form";
 import { Channel, invoke } from "@tauri-apps/api/core";
 import { useState } from "react";
 import { save } from "@tauri-apps/plugin-dialog";
@@ -34,6 +34,20 @@ const electionSchema = z
     question: z.string().min(1),
     choices: z.string().min(1),
     signature_required: z.boolean(),
+    full_balance: z.boolean(),
+    organizer_key: z.string().regex(/^([0-9a-fA-F]{64})?$/, "Must be 64 hex characters"),
+    threshold: z.number().int().min(0).max(255),
+    trustees: z.number().int().min(0).max(255),
+    questions: z.array(
+      z.object({
+        question: z.string().min(1),
+        choices: z.string().min(1),
+      })
+    ),
+  })
+  .refine((d) => d.threshold <= d.trustees, {
+    message: "Threshold cannot exceed the number of trustees",
+    path: ["threshold"],
   })
   .refine((d) => d.end >= d.start, {
     message: "End must be higher than Start",
@@ -42,12 +56,14 @@ const electionSchema = z
 
 type ElectionData = {
   seed: string;
+  shares: string[];
   election: any;
 };
 
 function App() {
   const [progress, setProgress] = useState<number | undefined>();
   const [seed, setSeed] = useState<string>("");
+  const [shares, setShares] = useState<string[]>([]);
   const [election, setElection] = useState<any>();
   const [showSeed, setShowSeed] = useState(false);
   const [creating, setCreating] = useState(false);
@@ -61,10 +77,28 @@ function App() {
       question: "",
       choices: "",
       signature_required: false,
+      full_balance: false,
+      organizer_key: "",
+      threshold: 0,
+      trustees: 0,
+      questions: [],
     },
   });
 
   const { control, handleSubmit } = form;
+  const { fields, append, remove } = useFieldArray({ control, name: "questions" });
+
+  const generateOrganizerKey = () => {
+    (async () => {
+      const key: { secret_key: string; public_key: string } = await invoke("new_organizer_key");
+      form.setValue("organizer_key", key.secret_key);
+      await Swal.fire({
+        icon: "info",
+        title: "Organizer Key",
+        html: `Save the secret key to sign future elections.<br/>Voters should pin the public key:<br/><code>${key.public_key}</code>`,
+      });
+    })();
+  };
 
   const steps = [
     {
@@ -85,12 +119,28 @@ function App() {
     },
     {
       target: ".choices",
-      content: "The possible answers. Only one answer per vote. But multiple votes can be cast as long as the elector has enough funds.",
+      content: "The possible answers. Only one answer per vote. But multiple votes can be cast as long as the elector has enough funds.\nCandidates can receive the votes on their own address. Otherwise, it is derived from the election seed.",
+    },
+    {
+      target: ".add-question",
+      content: "Add more questions to the same election. Electors vote on each question separately with the same coins.",
+    },
+    {
+      target: ".full-balance",
+      content: "If checked, the voting power is the whole Orchard balance at the end height, regardless of when the coins were received. The registration start is ignored and the snapshot takes much longer to build.",
     },
     {
       target: ".signature",
       content: "Check if this election should require the electors to include a signature (proving ownership).\nIf unchecked, electors only need the viewing key.",
     },
+    {
+      target: ".organizer",
+      content: "Optional secret key of the organizer. If set, the election file is signed and voters can check that it was published by you.",
+    },
+    {
+      target: ".trustees",
+      content: "Optionally, split the election seed between trustees. Any group of 'threshold' trustees can count the votes. Leave at 0 to keep a single seed.",
+    },
   ];
 
   const onSubmit: SubmitHandler<z.infer<typeof electionSchema>> = (data) => {
@@ -103,18 +153,28 @@ function App() {
       try {
         setCreating(true);
         const election: string = await invoke("create_election", {
-          election: data,
+          election: {
+            ...data,
+            snapshot: data.full_balance ? "full_balance" : "range",
+            threshold: data.trustees > 0 ? data.threshold : undefined,
+            trustees: data.trustees > 0 ? data.trustees : undefined,
+          },
           channel: channel,
         });
         const electionData: ElectionData = JSON.parse(election);
         const seed = electionData.seed;
         setSeed(seed);
+        setShares(electionData.shares ?? []);
         setElection(electionData.election);
-        setShowSeed(true);
+        if (seed || electionData.shares?.length) {
+          setShowSeed(true);
+        } else {
+          saveElectionFile(electionData.election);
+        }
       } catch (e: any) {
         await Swal.fire({
           icon: "error",
-          title: e,
+          title: e.message ?? e,
         });
       }
       finally {
@@ -123,7 +183,7 @@ function App() {
     })();
   };
 
-  const saveElectionFile = () => {
+  const saveElectionFile = (election: any) => {
     (async () => {
       setShowSeed(false);
       const id = await invoke('get_election_id', { election: election });
@@ -237,7 +297,74 @@ function App() {
                   <FormControl>
                     <Textarea {...field} rows={10} />
                   </FormControl>
-                  <FormDescription>Use one line per choice</FormDescription>
+                  <FormDescription>
+                    Use one line per choice. End a line with "| zvote1..." if
+                    the candidate supplies its own address
+                  </FormDescription>
+                  <FormMessage />
+                </FormItem>
+              )}
+            />
+
+            {fields.map((item, index) => (
+              <div key={item.id} className="flex flex-col gap-4 border rounded-md p-2">
+                <FormField
+                  control={control}
+                  name={`questions.${index}.question`}
+                  render={({ field }) => (
+                    <FormItem>
+                      <FormLabel>Question #{index + 2}</FormLabel>
+                      <FormControl>
+                        <Input {...field} />
+                      </FormControl>
+                      <FormMessage />
+                    </FormItem>
+                  )}
+                />
+                <FormField
+                  control={control}
+                  name={`questions.${index}.choices`}
+                  render={({ field }) => (
+                    <FormItem>
+                      <FormLabel>Choices</FormLabel>
+                      <FormControl>
+                        <Textarea {...field} rows={5} />
+                      </FormControl>
+                      <FormMessage />
+                    </FormItem>
+                  )}
+                />
+                <Button type="button" variant="outline" onClick={() => remove(index)}>
+                  Remove Question
+                </Button>
+              </div>
+            ))}
+            <Button
+              type="button"
+              variant="outline"
+              className="add-question"
+              onClick={() => append({ question: "", choices: "" })}
+            >
+              Add Question
+            </Button>
+
+            <FormField
+              control={control}
+              name="full_balance"
+              render={({ field }) => (
+                <FormItem className="full-balance">
+                  <div className="flex items-center justify-between">
+                    <FormLabel>Full Balance</FormLabel>
+                    <FormControl>
+                      <Switch
+                        checked={field.value}
+                        onCheckedChange={field.onChange}
+                      />
+                    </FormControl>
+                  </div>
+                  <FormDescription>
+                    If checked, every coin unspent at the end height qualifies
+                  </FormDescription>
                   <FormMessage />
                 </FormItem>
               )}
@@ -265,6 +392,69 @@ function App() {
               )}
             />
 
+            <FormField
+              control={control}
+              name="organizer_key"
+              render={({ field }) => (
+                <FormItem className="organizer">
+                  <FormLabel>Organizer Key</FormLabel>
+                  <div className="flex gap-2">
+                    <FormControl>
+                      <Input type="password" {...field} />
+                    </FormControl>
+                    <Button type="button" onClick={generateOrganizerKey}>
+                      New
+                    </Button>
+                  </div>
+                  <FormDescription>
+                    If set, the election is signed by the organizer
+                  </FormDescription>
+                  <FormMessage />
+                </FormItem>
+              )}
+            />
+
+            <div className="flex gap-4">
+              <FormField
+                control={control}
+                name="threshold"
+                render={({ field }) => (
+                  <FormItem className="trustees">
+                    <FormLabel>Threshold</FormLabel>
+                    <FormControl>
+                      <Input
+                        type="number"
+                        {...field}
+                        onChange={(v) =>
+                          v && field.onChange(v.target.valueAsNumber)
+                        }
+                      />
+                    </FormControl>
+                    <FormMessage />
+                  </FormItem>
+                )}
+              />
+              <FormField
+                control={control}
+                name="trustees"
+                render={({ field }) => (
+                  <FormItem>
+                    <FormLabel>Trustees</FormLabel>
+                    <FormControl>
+                      <Input
+                        type="number"
+                        {...field}
+                        onChange={(v) =>
+                          v && field.onChange(v.target.valueAsNumber)
+                        }
+                      />
+                    </FormControl>
+                    <FormMessage />
+                  </FormItem>
+                )}
+              />
+            </div>
+
             <Button className="my-4" onClick={() => {}} type="submit">
               Create Election File
             </Button>
@@ -281,9 +471,24 @@ function App() {
                 You MUST save these 24 words in the correct order and spelling.
                 It is impossible to decode the votes without them.
               </h3>
-              <h4 className="text-lg mb-4 border border-red-400 p-1">{seed}</h4>
+              {seed && (
+                <h4 className="text-lg mb-4 border border-red-400 p-1">{seed}</h4>
+              )}
+              {shares.length > 0 && (
+                <div className="mb-4">
+                  <p className="mb-2">
+                    Give one share to each trustee. The votes can only be
+                    counted when enough trustees combine their shares.
+                  </p>
+                  {shares.map((s, i) => (
+                    <h4 key={i} className="text-sm mb-1 border border-red-400 p-1 break-all">
+                      {s}
+                    </h4>
+                  ))}
+                </div>
+              )}
               <div className="flex justify-center gap-4">
-                <Button onClick={saveElectionFile}>
+                <Button onClick={() => saveElectionFile(election)}>
                   OK, I have saved them
                 </Button>
               </div>
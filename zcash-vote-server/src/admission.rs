@@ -0,0 +1,336 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use orchard::vote::{Ballot, OrchardHash};
+use rocket::{
+    request::{FromRequest, Outcome},
+    tokio::sync::{OwnedSemaphorePermit, Semaphore},
+    Request,
+};
+use rusqlite::Connection;
+use zcash_vote::{
+    as_byte256,
+    election::{Election, BALLOT_VK},
+    errors::VoteError,
+};
+
+use crate::{
+    context::Context,
+    db::{check_cmx_root, get_election, get_last_frontier, is_spent},
+};
+
+/// Clients that have not posted a ballot for that long
+/// start again with a full allowance
+const IDLE_CLIENT: Duration = Duration::from_secs(600);
+
+/// Longest batch of ballots, the ballots of one allocation
+pub const MAX_BATCH_BALLOTS: usize = 16;
+
+/// Sighashes of the ballots whose proofs and signatures are valid,
+/// shared by the REST API and the vote chain of a node
+pub type VerifiedBallots = Arc<Mutex<HashSet<String>>>;
+
+/// Check a batch of ballots against the committed state of its election:
+/// the election is open, the proofs and signatures are valid, the
+/// anchors are roots of the election and the notes are unspent.
+/// Returns the hex encoded sighashes
+///
+/// The first ballot may be anchored on any root of the election. Every
+/// other ballot spends the change of the ballots before it, and must be
+/// anchored on the root after them, appended to the last ballot of the
+/// election. The ballots of a batch spend different notes
+///
+/// Verifying the proofs is CPU intensive, they are not verified again
+/// for the ballots whose sighash is in `verified`. The sighashes of the
+/// batch are added to it once the whole batch passes
+pub fn check_batch(
+    connection: &Connection,
+    id: &str,
+    ballots: &[Ballot],
+    verified: &Mutex<HashSet<String>>,
+) -> Result<Vec<String>, VoteError> {
+    if ballots.is_empty() || ballots.len() > MAX_BATCH_BALLOTS {
+        return Err(VoteError::InvalidBallot(format!(
+            "a batch has 1 to {MAX_BATCH_BALLOTS} ballots"
+        )));
+    }
+    let (id_election, election, closed) = get_election(connection, id).map_err(VoteError::from)?;
+    if closed {
+        return Err(VoteError::ClosedElection);
+    }
+    let election = serde_json::from_str::<Election>(&election)
+        .map_err(|e| VoteError::InvalidJson(e.to_string()))?;
+    // frontier after the ballots checked so far, when some are chained
+    let mut frontier = match ballots.len() {
+        1 => None,
+        _ => Some(get_last_frontier(connection, id_election).map_err(VoteError::from)?.1),
+    };
+    let mut dnfs = HashSet::new();
+    let mut sighashes = vec![];
+    for (i, ballot) in ballots.iter().enumerate() {
+        let sighash = ballot
+            .data
+            .sighash()
+            .map_err(|e| VoteError::InvalidBallot(e.to_string()))?;
+        let sighash = hex::encode(sighash);
+        // check ballot zkp, and signatures
+        let known = verified.lock().unwrap().contains(&sighash);
+        if !known {
+            orchard::vote::validate_ballot(
+                ballot.clone(),
+                election.signature_required,
+                &BALLOT_VK,
+            )?;
+        }
+        tracing::info!("Checking ballot {}", sighash);
+        let data = &ballot.data;
+
+        // check that the public data matches with the election params
+        // nf_root & cmx_root
+        if data.anchors.nf != election.nf.0 {
+            return Err(VoteError::BadRoot("nullifier".to_string()));
+        }
+        if i == 0 {
+            check_cmx_root(connection, id_election, &data.anchors.cmx).map_err(VoteError::from)?;
+        }
+        if let Some(frontier) = &mut frontier {
+            if i > 0 && data.anchors.cmx[..] != frontier.root()[..] {
+                return Err(VoteError::BadRoot("cmx".to_string()));
+            }
+            for a in data.actions.iter() {
+                frontier.append(OrchardHash(as_byte256(&a.cmx)));
+            }
+        }
+
+        // check that we are not double spending a previous note,
+        // or a note of the batch
+        check_unspent(connection, id_election, data.actions.iter().map(|a| a.nf.as_slice()))?;
+        for a in data.actions.iter() {
+            if !dnfs.insert(a.nf.clone()) {
+                return Err(VoteError::DoubleNullifier(hex::encode(&a.nf)));
+            }
+        }
+        sighashes.push(sighash);
+    }
+    verified.lock().unwrap().extend(sighashes.iter().cloned());
+    Ok(sighashes)
+}
+
+/// Fails with the first domain nullifier that a committed
+/// ballot of the election has spent already
+pub fn check_unspent<'a>(
+    connection: &Connection,
+    id_election: u32,
+    dnfs: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<(), VoteError> {
+    for dnf in dnfs {
+        if is_spent(connection, id_election, dnf).map_err(VoteError::from)? {
+            return Err(VoteError::DoubleNullifier(hex::encode(dnf)));
+        }
+    }
+    Ok(())
+}
+
+/// Admission control of the ballots posted to the REST API,
+/// before they are verified and relayed to CometBFT
+pub struct Admission {
+    /// Ballots accepted per minute from one client, 0 for no limit
+    ballots_per_minute: u32,
+    /// The server is behind a proxy that sets the client address
+    /// in the `ip_header` of the Rocket config
+    trusted_proxy: bool,
+    /// Allowance left of each client, and when it was last updated
+    clients: Mutex<HashMap<IpAddr, (Instant, f64)>>,
+    /// One permit per ballot being verified
+    queue: Arc<Semaphore>,
+}
+
+impl Admission {
+    pub fn new(ballots_per_minute: u32, max_pending: usize, trusted_proxy: bool) -> Self {
+        Self {
+            ballots_per_minute,
+            trusted_proxy,
+            clients: Mutex::new(HashMap::new()),
+            queue: Arc::new(Semaphore::new(max_pending)),
+        }
+    }
+
+    /// Admit a batch of `n` ballots from `client` for verification
+    ///
+    /// Fails when the client has used up its allowance or when the
+    /// verification queue is full. The batch keeps its place in the
+    /// queue until the permit is dropped
+    pub fn admit(&self, client: IpAddr, n: usize) -> Result<OwnedSemaphorePermit, VoteError> {
+        self.take_tokens(client, n as f64, Instant::now())?;
+        self.queue
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| VoteError::RateLimited("verification queue is full".to_string()))
+    }
+
+    /// Token bucket that refills continuously, up to one minute of ballots
+    fn take_tokens(&self, client: IpAddr, n: f64, now: Instant) -> Result<(), VoteError> {
+        if self.ballots_per_minute == 0 {
+            return Ok(());
+        }
+        let capacity = self.ballots_per_minute as f64;
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|_, (last, _)| now.duration_since(*last) < IDLE_CLIENT);
+        let (last, tokens) = clients.entry(client).or_insert((now, capacity));
+        let refill = now.duration_since(*last).as_secs_f64() * capacity / 60.0;
+        *tokens = (*tokens + refill).min(capacity);
+        *last = now;
+        if *tokens < n {
+            return Err(VoteError::RateLimited(format!(
+                "more than {} ballots per minute",
+                self.ballots_per_minute
+            )));
+        }
+        *tokens -= n;
+        Ok(())
+    }
+}
+
+/// Address of the client that the allowance is counted for. It is the
+/// address of the connection, since any client can set `X-Real-IP`,
+/// unless the proxy in front of the server is trusted to set it.
+/// Clients without a known address share one allowance
+pub struct ClientAddress(pub IpAddr);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientAddress {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Infallible> {
+        let trusted_proxy = request
+            .rocket()
+            .state::<Context>()
+            .is_some_and(|c| c.admission.trusted_proxy);
+        let ip = match trusted_proxy {
+            true => request.client_ip(),
+            false => request.remote().map(|r| r.ip()),
+        };
+        Outcome::Success(ClientAddress(ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{
+        http::{Header, Status},
+        local::asynchronous::Client,
+        State,
+    };
+
+    use super::*;
+
+    fn client(n: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, n))
+    }
+
+    #[test]
+    fn allowances_refill_up_to_one_minute() {
+        let admission = Admission::new(60, 16, false);
+        let start = Instant::now();
+        admission.take_tokens(client(1), 60.0, start).unwrap();
+        assert!(admission.take_tokens(client(1), 1.0, start).is_err());
+        // other clients have their own allowance
+        admission.take_tokens(client(2), 1.0, start).unwrap();
+
+        // one ballot per second
+        let later = start + Duration::from_secs(2);
+        admission.take_tokens(client(1), 2.0, later).unwrap();
+        assert!(admission.take_tokens(client(1), 1.0, later).is_err());
+
+        // the allowance does not grow past one minute of ballots
+        let later = later + Duration::from_secs(300);
+        assert!(admission.take_tokens(client(1), 61.0, later).is_err());
+        admission.take_tokens(client(1), 60.0, later).unwrap();
+    }
+
+    #[test]
+    fn batches_are_admitted_whole() {
+        let admission = Admission::new(5, 16, false);
+        let start = Instant::now();
+        admission.take_tokens(client(1), 3.0, start).unwrap();
+        // a rejected batch takes nothing
+        assert!(admission.take_tokens(client(1), 3.0, start).is_err());
+        admission.take_tokens(client(1), 2.0, start).unwrap();
+
+        let unlimited = Admission::new(0, 16, false);
+        unlimited.take_tokens(client(1), 1000.0, start).unwrap();
+    }
+
+    #[test]
+    fn idle_clients_are_forgotten() {
+        let admission = Admission::new(60, 16, false);
+        let start = Instant::now();
+        admission.take_tokens(client(1), 60.0, start).unwrap();
+        let idle = start + IDLE_CLIENT;
+        admission.take_tokens(client(2), 1.0, idle).unwrap();
+        assert_eq!(admission.clients.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn the_verification_queue_is_bounded() {
+        let admission = Admission::new(0, 1, false);
+        let permit = admission.admit(client(1), 1).unwrap();
+        assert!(matches!(
+            admission.admit(client(2), 1),
+            Err(VoteError::RateLimited(_))
+        ));
+        drop(permit);
+        admission.admit(client(2), 1).unwrap();
+    }
+
+    #[rocket::post("/admit")]
+    fn admit(client: ClientAddress, state: &State<Context>) -> Status {
+        match state.admission.admit(client.0, 1) {
+            Ok(_) => Status::Ok,
+            Err(_) => Status::TooManyRequests,
+        }
+    }
+
+    /// Statuses of three ballots from one connection, each
+    /// with another `X-Real-IP`, with an allowance of two
+    async fn spoofed_statuses(trusted_proxy: bool) -> Vec<Status> {
+        let context = Context::new(
+            String::new(),
+            ":memory:".to_string(),
+            0,
+            None,
+            Admission::new(2, 16, trusted_proxy),
+            Duration::from_secs(1),
+        );
+        let rocket = rocket::build()
+            .manage(context)
+            .mount("/", rocket::routes![admit]);
+        let client = Client::untracked(rocket).await.unwrap();
+        let mut statuses = vec![];
+        for n in 1..=3 {
+            let response = client
+                .post("/admit")
+                .remote("192.0.2.1:4000".parse().unwrap())
+                .header(Header::new("X-Real-IP", format!("198.51.100.{n}")))
+                .dispatch()
+                .await;
+            statuses.push(response.status());
+        }
+        statuses
+    }
+
+    #[rocket::async_test]
+    async fn spoofed_addresses_do_not_reset_the_allowance() {
+        assert_eq!(
+            spoofed_statuses(false).await,
+            [Status::Ok, Status::Ok, Status::TooManyRequests]
+        );
+        // behind a trusted proxy, the header is the address of the client
+        assert_eq!(spoofed_statuses(true).await, [Status::Ok; 3]);
+    }
+}
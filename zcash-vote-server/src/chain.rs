@@ -1,37 +1,92 @@
 use anyhow::Result;
 use blake2b_simd::Params;
-use rusqlite::{params, OptionalExtension};
+use rusqlite::params;
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
+    collections::{HashMap, HashSet},
     sync::mpsc::{channel, Receiver, Sender},
 };
 use zcash_vote::{
     as_byte256,
+    client::{BallotEvent, SubmissionStatus},
     db::{load_prop, store_dnf, store_prop},
-    election::{Election, BALLOT_VK},
+    errors::{ErrorCode, ErrorResponse, VoteError},
 };
 
 use orchard::vote::{Ballot, Frontier, OrchardHash};
 use r2d2::PooledConnection;
+use rocket::tokio::sync::broadcast;
 use r2d2_sqlite::SqliteConnectionManager;
 use tendermint_abci::Application;
 use tendermint_proto::abci::{
-    ExecTxResult, RequestCheckTx, RequestFinalizeBlock, RequestInfo, RequestPrepareProposal,
+    Event, EventAttribute, ExecTxResult, RequestCheckTx, RequestFinalizeBlock, RequestInfo, RequestPrepareProposal,
     RequestQuery, ResponseCheckTx, ResponseCommit, ResponseFinalizeBlock, ResponseInfo,
     ResponsePrepareProposal, ResponseQuery,
 };
 
 use crate::{
-    db::{check_cmx_root, get_election, store_ballot, AppState},
-    routes::Tx,
+    admission::{check_batch, check_unspent, VerifiedBallots},
+    db::{get_election, get_last_frontier, is_spent, store_ballot, store_submission, AppState},
+    routes::BatchTx,
 };
 
+/// Parts of a ballot that the vote chain stores. Its proofs and
+/// signatures are verified by check_tx
+#[cfg_attr(test, derive(serde::Serialize, serde::Deserialize))]
+pub struct BallotTx {
+    /// Id of the election
+    pub id: String,
+    pub sighash: Vec<u8>,
+    /// Root of the commitment tree that the ballot is anchored on
+    pub anchor: Vec<u8>,
+    /// Domain nullifiers of the notes spent
+    pub dnfs: Vec<Vec<u8>>,
+    /// Commitments of the new notes
+    pub cmxs: Vec<Vec<u8>>,
+    /// JSON of the ballot, as served to the clients
+    pub data: String,
+}
+
+impl BallotTx {
+    pub fn new(id: &str, ballot: &Ballot) -> Result<Self> {
+        let data = &ballot.data;
+        Ok(BallotTx {
+            id: id.to_string(),
+            sighash: data.sighash()?.to_vec(),
+            anchor: data.anchors.cmx.to_vec(),
+            dnfs: data.actions.iter().map(|a| a.nf.to_vec()).collect(),
+            cmxs: data.actions.iter().map(|a| a.cmx.to_vec()).collect(),
+            data: serde_json::to_string(ballot)?,
+        })
+    }
+
+    /// The ballots of a transaction
+    pub fn from_batch(batch: &BatchTx) -> Result<Vec<Self>> {
+        batch
+            .ballots
+            .iter()
+            .map(|ballot| Self::new(&batch.id, ballot))
+            .collect()
+    }
+}
+
+/// Ballots of a transaction of a block
+fn decode_block_tx(tx: &[u8]) -> Result<Vec<BallotTx>> {
+    // test ballots have no proofs, they only go in blocks
+    #[cfg(test)]
+    if let Some(tx) = tx.strip_prefix(crate::testing::TEST_TX) {
+        return Ok(bincode::deserialize(tx)?);
+    }
+    let batch = BatchTx::decode(tx)?;
+    BallotTx::from_batch(&batch)
+}
+
 pub enum Command {
     Stop,
     Info(Sender<AppState>),
-    CheckBallot(String, Ballot, Sender<Result<String, String>>),
-    PrepareProposal(String, Ballot, Sender<Option<String>>),
-    FinalizeBallot(String, Ballot, Sender<Result<String, String>>),
+    CheckBatch(BatchTx, Sender<Result<Vec<String>, ErrorResponse>>),
+    PrepareProposal(Vec<Vec<BallotTx>>, Sender<Vec<Result<(), ErrorResponse>>>),
+    BeginBlock,
+    FinalizeBatch(Vec<BallotTx>, Sender<Result<Vec<BallotEvent>, ErrorResponse>>),
     Commit(Sender<AppState>),
 }
 
@@ -41,14 +96,19 @@ pub struct VoteChain {
 }
 
 impl VoteChain {
-    pub fn new(connection: PooledConnection<SqliteConnectionManager>) -> (Self, VoteChainRunner) {
+    pub fn new(
+        connection: PooledConnection<SqliteConnectionManager>,
+        events: broadcast::Sender<BallotEvent>,
+        verified: VerifiedBallots,
+    ) -> (Self, VoteChainRunner) {
         let (cmd_tx, cmd_rx) = channel::<Command>();
         let s = Self { cmd_tx };
         let r = VoteChainRunner {
             connection,
             cmd_rx,
-            check_cache: HashMap::new(),
-            dnfs: HashSet::new(),
+            verified,
+            events,
+            finalized: vec![],
         };
         (s, r)
     }
@@ -80,33 +140,41 @@ impl Application for VoteChain {
     fn check_tx(&self, request: RequestCheckTx) -> ResponseCheckTx {
         tracing::info!(
             "check_tx --> {} TYPE {}",
-            hex::encode(&request.tx[0..16]),
+            hex::encode(&request.tx[..request.tx.len().min(16)]),
             request.r#type
         );
 
-        let Tx { id, ballot } = bincode::deserialize(&request.tx).unwrap();
-        let (tx_result, rx_result) = channel();
-        self.cmd_tx
-            .send(Command::CheckBallot(id, ballot, tx_result))
-            .map_err(anyhow::Error::msg)
-            .unwrap();
-
-        let res = rx_result.recv().unwrap();
+        let res = match BatchTx::decode(&request.tx) {
+            Ok(batch) => {
+                let (tx_result, rx_result) = channel();
+                self.cmd_tx
+                    .send(Command::CheckBatch(batch, tx_result))
+                    .map_err(anyhow::Error::msg)
+                    .unwrap();
+                rx_result.recv().unwrap()
+            }
+            Err(e) => Err(ErrorResponse {
+                code: ErrorCode::InvalidData,
+                message: e.to_string(),
+            }),
+        };
         match res {
-            Ok(hash) => {
-                tracing::info!("check_tx ok: {}", hash);
+            Ok(hashes) => {
+                let hashes = hashes.join(",");
+                tracing::info!("check_tx ok: {}", hashes);
                 ResponseCheckTx {
                     code: 0,
-                    data: hash.into(),
+                    data: hashes.into(),
                     ..Default::default()
                 }
             }
 
-            Err(message) => {
-                tracing::error!("check_tx failed: {}", message);
+            Err(err) => {
+                tracing::error!("check_tx failed: {}", err.message);
                 ResponseCheckTx {
-                    code: 1,
-                    data: message.into(),
+                    code: err.code.abci_code(),
+                    data: err.message.clone().into(),
+                    log: err.message,
                     ..Default::default()
                 }
             }
@@ -114,52 +182,61 @@ impl Application for VoteChain {
     }
 
     fn prepare_proposal(&self, request: RequestPrepareProposal) -> ResponsePrepareProposal {
-        let mut filtered_txs = vec![];
+        let mut txs = vec![];
+        let mut batches = vec![];
         for tx in request.txs.into_iter() {
-            let Tx { id, ballot } = bincode::deserialize(&tx).unwrap();
-            let sighash = hex::encode(&ballot.data.sighash().unwrap());
-            let (tx_result, rx_result) = channel();
-            self.cmd_tx
-                .send(Command::PrepareProposal(id, ballot, tx_result))
-                .map_err(anyhow::Error::msg)
-                .unwrap();
-            let res = rx_result.recv().unwrap();
+            match decode_block_tx(&tx) {
+                Ok(batch) => {
+                    txs.push(tx);
+                    batches.push(batch);
+                }
+                Err(error) => tracing::error!("prepare_proposal: {}", error),
+            }
+        }
+        let sighashes = batches
+            .iter()
+            .map(|b| b.iter().map(|b| hex::encode(&b.sighash)).collect::<Vec<_>>().join(","))
+            .collect::<Vec<_>>();
+        let (tx_result, rx_result) = channel();
+        self.cmd_tx
+            .send(Command::PrepareProposal(batches, tx_result))
+            .map_err(anyhow::Error::msg)
+            .unwrap();
+        let res = rx_result.recv().unwrap();
+
+        let mut filtered_txs = vec![];
+        for ((tx, sighash), res) in txs.into_iter().zip(sighashes).zip(res) {
             match res {
-                None => {
+                Ok(()) => {
                     tracing::info!("prepare_proposal: {}", sighash);
                     filtered_txs.push(tx)
                 }
-                Some(error) => tracing::error!("prepare_proposal: {}", error),
+                Err(error) => tracing::error!("prepare_proposal: {} {}", sighash, error.message),
             }
         }
         ResponsePrepareProposal { txs: filtered_txs }
     }
 
     fn finalize_block(&self, request: RequestFinalizeBlock) -> ResponseFinalizeBlock {
+        self.cmd_tx
+            .send(Command::BeginBlock)
+            .map_err(anyhow::Error::msg)
+            .unwrap();
         let mut tx_results = vec![];
         for tx in request.txs.iter() {
-            let Tx { id, ballot } = bincode::deserialize(&tx).unwrap();
-            let (tx_result, rx_result) = channel();
-            self.cmd_tx
-                .send(Command::FinalizeBallot(id, ballot, tx_result))
-                .map_err(anyhow::Error::msg)
-                .unwrap();
-            let res = rx_result.recv().unwrap();
-            tracing::info!("finalize_block: {:?}", res);
-
-            let tx_result = match res {
-                Ok(_) => ExecTxResult {
-                    code: 0,
-                    log: "Validated".to_string(),
-                    ..Default::default()
-                },
-                Err(err) => ExecTxResult {
-                    code: 1,
-                    log: format!("Validation failed: {}", err),
-                    ..Default::default()
-                },
+            let res = match decode_block_tx(tx) {
+                Ok(txs) => {
+                    let (tx_result, rx_result) = channel();
+                    self.cmd_tx
+                        .send(Command::FinalizeBatch(txs, tx_result))
+                        .map_err(anyhow::Error::msg)
+                        .unwrap();
+                    rx_result.recv().unwrap()
+                }
+                Err(e) => Err(ErrorResponse::from(VoteError::from(e))),
             };
-            tx_results.push(tx_result);
+            tracing::info!("finalize_block: {:?}", res);
+            tx_results.push(exec_tx_result(res));
         }
 
         let (tx_result, rx_result) = channel();
@@ -194,8 +271,13 @@ impl Application for VoteChain {
 pub struct VoteChainRunner {
     connection: PooledConnection<SqliteConnectionManager>,
     cmd_rx: Receiver<Command>,
-    check_cache: HashMap<String, Result<String, String>>,
-    dnfs: HashSet<String>,
+    /// Sighashes of the ballots whose proofs and signatures are valid,
+    /// also those verified by the REST API. Their state is checked
+    /// again by every check_tx
+    verified: VerifiedBallots,
+    events: broadcast::Sender<BallotEvent>,
+    /// Ballots of the current block, published on commit
+    finalized: Vec<BallotEvent>,
 }
 
 impl VoteChainRunner {
@@ -212,194 +294,259 @@ impl VoteChainRunner {
                 let app_state = Self::get_state(&self.connection);
                 result.send(app_state).unwrap();
             }
-            Command::CheckBallot(id, ballot, result) => {
-                let sighash = hex::encode(ballot.data.sighash().unwrap());
-                let r = match self.check_cache.entry(sighash.clone()) {
-                    Entry::Occupied(r) => r.get().clone(),
-                    Entry::Vacant(ve) => {
-                        let res = || {
-                            let connection = &self.connection;
-                            let (id_election, election, closed) =
-                                get_election(connection, &id).map_err(|e| e.to_string())?;
-                            if closed {
-                                return Err("Election is closed".to_string());
-                            }
-                            let election = serde_json::from_str::<Election>(&election)
-                                .map_err(|e| e.to_string())?;
-                            // check ballot zkp, and signatures
-                            let data = orchard::vote::validate_ballot(
-                                ballot.clone(),
-                                election.signature_required,
-                                &BALLOT_VK,
-                            )
-                            .map_err(|e| e.to_string())?;
-                            tracing::info!("Checking ballot {}", sighash);
-
-                            // check that the public data matches with the election params
-                            // nf_root & cmx_root
-                            if data.anchors.nf != election.nf.0 {
-                                return Err("Incorrect nullifier root".to_string());
-                            }
-                            check_cmx_root(connection, id_election, &data.anchors.cmx)
-                                .map_err(|e| e.to_string())?;
-
-                            // check that we are not double spending a previous note
-                            for action in data.actions.iter() {
-                                let dnf = &action.nf;
-                                let exists = connection
-                                    .query_row(
-                                        "SELECT 1 FROM dnfs WHERE election = ?1 AND hash = ?2",
-                                        params![&election.id(), dnf],
-                                        |_| Ok(()),
-                                    )
-                                    .optional()
-                                    .unwrap()
-                                    .is_some();
-                                if exists {
-                                    return Err("Duplicate nullifier: double spend".to_string());
-                                }
-                            }
-                            Ok::<_, String>(sighash.clone())
-                        };
-                        let r = res();
-                        ve.insert_entry(r.clone());
-                        r
-                    }
-                };
-
+            Command::CheckBatch(batch, result) => {
+                // errors are not kept, the state may change
+                let r = check_batch(&self.connection, &batch.id, &batch.ballots, &self.verified)
+                    .map_err(ErrorResponse::from);
                 result.send(r).unwrap();
             }
-            Command::PrepareProposal(_, ballot, sender) => {
-                for a in ballot.data.actions.iter() {
-                    let dnf = hex::encode(&a.nf);
-                    let new_spend = self.dnfs.insert(dnf);
-                    if !new_spend {
-                        sender.send(Some("Double spend".to_string()))?;
-                    }
-                    else {
-                        sender.send(None)?;
-                    }
-                }
+            Command::PrepareProposal(batches, sender) => {
+                sender.send(self.prepare_proposal(batches))?;
             }
-            Command::FinalizeBallot(id, ballot, result) => {
-                let connection = &self.connection;
-                let mut res = || {
-                    let _ = connection.execute("ROLLBACK", []); // Ignore error
-                    connection.execute("BEGIN TRANSACTION", [])?;
-
-                    let (id_election, _, closed) = get_election(connection, &id)?;
-                    if closed {
-                        anyhow::bail!("Election is closed");
-                    }
+            Command::BeginBlock => self.begin_block()?,
+            Command::FinalizeBatch(txs, result) => {
+                let res = self.finalize_batch(txs);
+                result.send(res).unwrap();
+            }
+            Command::Commit(result) => {
+                let app_state = self.commit()?;
+                result.send(app_state).unwrap();
+            }
+        }
 
-                    // election id, ballot zkp, signatures and
-                    // double spends were checked in check_tx
-                    let data = &ballot.data;
-
-                    let height = connection.query_row(
-                        "SELECT MAX(height) FROM cmx_frontiers WHERE election = ?1",
-                        [id_election],
-                        |r| r.get::<_, u32>(0),
-                    )?;
-
-                    let cmx_frontier = {
-                        // calculate the new cmx_frontier
-                        let cmx_frontier = connection.query_row(
-                            "SELECT frontier FROM cmx_frontiers WHERE election = ?1 AND height = ?2",
-                            params![id_election, height],
-                            |r| r.get::<_, String>(0),
-                        )?;
-                        let mut cmx_frontier = serde_json::from_str::<Frontier>(&cmx_frontier)?;
-                        for action in data.actions.iter() {
-                            cmx_frontier.append(OrchardHash(as_byte256(&action.cmx)));
-                            store_dnf(connection, id_election, &action.nf).map_err(|_| {
-                                anyhow::anyhow!("Duplicate nullifier: double spend")
-                            })?;
+        Ok(())
+    }
+
+    /// Check the transactions of a proposal, in order. A transaction is
+    /// left out when it spends a note spent by a committed ballot, by a
+    /// transaction already in the proposal or by another of its actions,
+    /// or when a chained ballot is not anchored on the root after the
+    /// ballots before it
+    pub(crate) fn prepare_proposal(
+        &self,
+        batches: &[Vec<BallotTx>],
+    ) -> Vec<Result<(), ErrorResponse>> {
+        let connection = &self.connection;
+        let mut proposed = HashSet::new();
+        // frontiers after the transactions of the proposal
+        let mut frontiers = HashMap::<u32, Frontier>::new();
+        let mut results = vec![];
+        for batch in batches.iter() {
+            let res = || {
+                let Some(first) = batch.first() else {
+                    return Err(VoteError::InvalidBallot("empty batch".to_string()).into());
+                };
+                let (id_election, _, _) = get_election(connection, &first.id)?;
+                let mut frontier = match frontiers.get(&id_election) {
+                    Some(frontier) => frontier.clone(),
+                    None => get_last_frontier(connection, id_election)?.1,
+                };
+                let mut batch_dnfs = HashSet::new();
+                for (i, ballot) in batch.iter().enumerate() {
+                    if i > 0 && ballot.anchor[..] != frontier.root()[..] {
+                        return Err(VoteError::BadRoot("cmx".to_string()).into());
+                    }
+                    check_unspent(connection, id_election, ballot.dnfs.iter().map(Vec::as_slice))?;
+                    for dnf in ballot.dnfs.iter() {
+                        let key = (id_election, dnf.clone());
+                        if proposed.contains(&key) || !batch_dnfs.insert(key) {
+                            return Err(VoteError::DoubleNullifier(hex::encode(dnf)).into());
                         }
-                        cmx_frontier
-                    };
-
-                    let cmx_root = cmx_frontier.root();
-                    {
-                        // store the new cmx_frontier
-                        let cmx_frontier = serde_json::to_string(&cmx_frontier)?;
-                        connection.execute(
-                            "INSERT INTO cmx_frontiers(election, height, frontier)
-                            VALUES (?1, ?2, ?3)",
-                            params![id_election, height + 1, &cmx_frontier],
-                        )?;
                     }
-
-                    let height = crate::db::get_num_ballots(connection, id_election)?;
-                    tracing::info!("ballot height: {height}");
-                    store_ballot(connection, id_election, height + 1, &ballot, &cmx_root)?;
-                    let sighash = hex::encode(data.sighash()?);
-                    tracing::info!("election: {id_election} sighash: {sighash}");
-
-                    let mut s = connection.prepare(
-                        "SELECT t1.hash, t1.election
-                        FROM cmx_roots t1
-                        JOIN (
-                            SELECT election, MAX(height) AS max_height
-                            FROM cmx_roots
-                            GROUP BY election
-                        ) t2
-                        ON t1.election = t2.election AND t1.height = t2.max_height",
-                    )?;
-                    let rows = s.query_map([], |r| {
-                        Ok((r.get::<_, Vec<u8>>(0)?, r.get::<_, u32>(1)?))
-                    })?;
-                    let mut hasher = Params::new()
-                        .hash_length(32)
-                        .personal(PERSO_VOTE_BFT)
-                        .to_state();
-                    for r in rows {
-                        let (h, _) = r?;
-                        hasher.update(&h);
+                    for cmx in ballot.cmxs.iter() {
+                        frontier.append(OrchardHash(as_byte256(cmx)));
                     }
-                    let hash = hasher.finalize();
-                    let hash = hash.as_bytes().to_vec();
-
-                    let app_state = Self::get_state(connection);
-                    let app_state = AppState {
-                        hash: hex::encode(&hash),
-                        ..app_state
-                    };
-                    store_prop(
-                        connection,
-                        "state",
-                        &serde_json::to_string(&app_state).unwrap(),
-                    )?;
-
-                    self.check_cache.remove(&sighash);
-                    self.dnfs.clear();
-                    tracing::info!("Ballot finalized");
-
-                    Ok::<_, anyhow::Error>(sighash)
-                };
+                }
+                Ok::<_, anyhow::Error>((id_election, frontier, batch_dnfs))
+            };
+            results.push(match res() {
+                Ok((id_election, frontier, batch_dnfs)) => {
+                    frontiers.insert(id_election, frontier);
+                    proposed.extend(batch_dnfs);
+                    Ok(())
+                }
+                Err(e) => Err(ErrorResponse::from(VoteError::from(e))),
+            });
+        }
+        results
+    }
+
+    /// The ballots of a block are written in one transaction,
+    /// committed by `commit`
+    pub(crate) fn begin_block(&mut self) -> Result<()> {
+        // a block that was finalized but never committed is dropped
+        let _ = self.connection.execute("ROLLBACK", []);
+        self.connection.execute("BEGIN TRANSACTION", [])?;
+        Ok(())
+    }
 
-                result.send(res().map_err(|e| e.to_string())).unwrap();
+    /// Add the ballots of a transaction to the block, all of them or
+    /// none. A transaction that fails is rolled back to its savepoint
+    /// and leaves the other transactions of the block. The rejection
+    /// of its ballots is then recorded in the block transaction
+    pub(crate) fn finalize_batch(
+        &mut self,
+        batch: &[BallotTx],
+    ) -> Result<Vec<BallotEvent>, ErrorResponse> {
+        let res = self
+            .connection
+            .execute("SAVEPOINT batch", [])
+            .map_err(anyhow::Error::from)
+            .and_then(|_| {
+                if batch.is_empty() {
+                    return Err(VoteError::InvalidBallot("empty batch".to_string()).into());
+                }
+                batch
+                    .iter()
+                    .enumerate()
+                    .map(|(i, tx)| self.apply_ballot(tx, i > 0))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .and_then(|events| {
+                self.connection.execute("RELEASE batch", [])?;
+                Ok(events)
+            });
+        let res = res.map_err(|e| ErrorResponse::from(VoteError::from(e)));
+        match &res {
+            Ok(events) => self.finalized.extend(events.iter().cloned()),
+            Err(e) => {
+                let _ = self.connection.execute("ROLLBACK TO batch", []);
+                let _ = self.connection.execute("RELEASE batch", []);
+                // the ballots passed check_tx but cannot be included
+                for tx in batch.iter() {
+                    let _ = store_submission(
+                        &self.connection,
+                        &tx.id,
+                        &tx.sighash,
+                        SubmissionStatus::Rejected,
+                        Some(e),
+                    );
+                }
             }
-            Command::Commit(result) => {
-                let connection = &self.connection;
-                let _ = connection.execute("COMMIT", []);
+        }
+        res
+    }
 
-                let app_state = Self::get_state(connection);
-                let app_state = AppState {
-                    height: app_state.height + 1,
-                    ..app_state
-                };
-                store_prop(
-                    connection,
-                    "state",
-                    &serde_json::to_string(&app_state).unwrap(),
-                )?;
+    /// Append a ballot to its election. A `chained` ballot must be
+    /// anchored on the root after the ballot before it
+    fn apply_ballot(&mut self, tx: &BallotTx, chained: bool) -> Result<BallotEvent> {
+        let connection = &self.connection;
+        let (id_election, _, closed) = get_election(connection, &tx.id)?;
+        if closed {
+            return Err(VoteError::ClosedElection.into());
+        }
 
-                result.send(app_state).unwrap();
+        // election id, ballot zkp, signatures and
+        // double spends were checked in check_tx
+        let (height, mut cmx_frontier) = get_last_frontier(connection, id_election)?;
+        if chained && tx.anchor[..] != cmx_frontier.root()[..] {
+            return Err(VoteError::BadRoot("cmx".to_string()).into());
+        }
+
+        // calculate the new cmx_frontier
+        for cmx in tx.cmxs.iter() {
+            cmx_frontier.append(OrchardHash(as_byte256(cmx)));
+        }
+        for dnf in tx.dnfs.iter() {
+            if is_spent(connection, id_election, dnf)? {
+                return Err(VoteError::DoubleNullifier(hex::encode(dnf)).into());
             }
+            store_dnf(connection, id_election, dnf)?;
         }
 
-        Ok(())
+        let cmx_root = cmx_frontier.root();
+        {
+            // store the new cmx_frontier
+            let cmx_frontier = serde_json::to_string(&cmx_frontier)?;
+            connection.execute(
+                "INSERT INTO cmx_frontiers(election, height, frontier)
+                VALUES (?1, ?2, ?3)",
+                params![id_election, height + 1, &cmx_frontier],
+            )?;
+        }
+
+        let height = crate::db::get_num_ballots(connection, id_election)?;
+        tracing::info!("ballot height: {height}");
+        store_ballot(
+            connection,
+            id_election,
+            height + 1,
+            &tx.sighash,
+            &tx.data,
+            &cmx_root,
+        )?;
+        let sighash = hex::encode(&tx.sighash);
+        tracing::info!("election: {id_election} sighash: {sighash}");
+        let event = BallotEvent {
+            election: tx.id.clone(),
+            height: height + 1,
+            sighash: sighash.clone(),
+            cmx_root: hex::encode(cmx_root),
+            dnfs: tx.dnfs.iter().map(hex::encode).collect(),
+        };
+
+        let mut s = connection.prepare(
+            "SELECT t1.hash, t1.election
+            FROM cmx_roots t1
+            JOIN (
+                SELECT election, MAX(height) AS max_height
+                FROM cmx_roots
+                GROUP BY election
+            ) t2
+            ON t1.election = t2.election AND t1.height = t2.max_height",
+        )?;
+        let rows = s.query_map([], |r| {
+            Ok((r.get::<_, Vec<u8>>(0)?, r.get::<_, u32>(1)?))
+        })?;
+        let mut hasher = Params::new()
+            .hash_length(32)
+            .personal(PERSO_VOTE_BFT)
+            .to_state();
+        for r in rows {
+            let (h, _) = r?;
+            hasher.update(&h);
+        }
+        let hash = hasher.finalize();
+        let hash = hash.as_bytes().to_vec();
+
+        let app_state = Self::get_state(connection);
+        let app_state = AppState {
+            hash: hex::encode(&hash),
+            ..app_state
+        };
+        store_prop(
+            connection,
+            "state",
+            &serde_json::to_string(&app_state).unwrap(),
+        )?;
+
+        self.verified.lock().unwrap().remove(&sighash);
+        tracing::info!("Ballot finalized");
+
+        Ok(event)
+    }
+
+    pub(crate) fn commit(&mut self) -> Result<AppState> {
+        let connection = &self.connection;
+        // subscribers only hear of ballots that are durably stored
+        let finalized = std::mem::take(&mut self.finalized);
+        connection.execute("COMMIT", [])?;
+        for event in finalized {
+            // fails only when nobody is listening
+            let _ = self.events.send(event);
+        }
+
+        let app_state = Self::get_state(connection);
+        let app_state = AppState {
+            height: app_state.height + 1,
+            ..app_state
+        };
+        store_prop(
+            connection,
+            "state",
+            &serde_json::to_string(&app_state).unwrap(),
+        )?;
+        Ok(app_state)
     }
 
     pub fn run(mut self) -> Result<()> {
@@ -420,4 +567,297 @@ impl VoteChainRunner {
     }
 }
 
+/// Result of a ballot in a finalized block. Only the ballots that
+/// were released into the block transaction have a `ballot` event,
+/// a rolled back ballot has its error code and no event
+fn exec_tx_result(res: Result<BallotEvent, ErrorResponse>) -> ExecTxResult {
+    match res {
+        Ok(event) => ExecTxResult {
+            code: 0,
+            log: "Validated".to_string(),
+            events: vec![abci_event(&event)],
+            ..Default::default()
+        },
+        Err(err) => ExecTxResult {
+            code: err.code.abci_code(),
+            log: format!("Validation failed: {}", err.message),
+            ..Default::default()
+        },
+    }
+}
+
+/// ABCI event of a finalized ballot, indexed by CometBFT so that
+/// `tx_search` finds ballots with queries such as
+/// `ballot.election='<id>'` or `ballot.sighash='<sighash>'`
+fn abci_event(event: &BallotEvent) -> Event {
+    let attribute = |key: &str, value: String| EventAttribute {
+        key: key.to_string(),
+        value,
+        index: true,
+    };
+    let mut attributes = vec![
+        attribute("election", event.election.clone()),
+        attribute("height", event.height.to_string()),
+        attribute("sighash", event.sighash.clone()),
+        attribute("cmx_root", event.cmx_root.clone()),
+    ];
+    attributes.extend(event.dnfs.iter().map(|dnf| attribute("dnf", dnf.clone())));
+    Event {
+        r#type: "ballot".to_string(),
+        attributes,
+    }
+}
+
 const PERSO_VOTE_BFT: &[u8] = b"Zcash_Vote_CmBFT";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{get_ballot_status, get_num_ballots},
+        testing::{ballot, open_election, root_after, test_pool, test_runner},
+    };
+
+    fn heights(connection: &rusqlite::Connection, id_election: u32) -> Vec<u32> {
+        let mut s = connection
+            .prepare("SELECT height FROM ballots WHERE election = ?1 ORDER BY height")
+            .unwrap();
+        let rows = s.query_map([id_election], |r| r.get::<_, u32>(0)).unwrap();
+        rows.collect::<Result<Vec<_>, _>>().unwrap()
+    }
+
+    #[test]
+    fn every_ballot_of_a_block_is_stored() {
+        let pool = test_pool();
+        let (id, id_election) = open_election(&pool.get().unwrap(), "block");
+        let mut runner = test_runner(&pool, broadcast::channel(16).0);
+
+        runner.begin_block().unwrap();
+        for n in 1..=3 {
+            runner.finalize_batch(&[ballot(&id, n, &[n])]).unwrap();
+        }
+        runner.commit().unwrap();
+
+        let connection = pool.get().unwrap();
+        assert_eq!(get_num_ballots(&connection, id_election).unwrap(), 3);
+        assert_eq!(heights(&connection, id_election), [1, 2, 3]);
+    }
+
+    #[test]
+    fn events_are_sent_for_committed_ballots() {
+        let pool = test_pool();
+        let (id, _) = open_election(&pool.get().unwrap(), "events");
+        let (events, mut rx) = broadcast::channel(16);
+        let mut runner = test_runner(&pool, events);
+
+        runner.begin_block().unwrap();
+        runner.finalize_batch(&[ballot(&id, 1, &[1])]).unwrap();
+        // spends the note of the first ballot
+        assert!(runner.finalize_batch(&[ballot(&id, 2, &[1])]).is_err());
+        assert!(rx.try_recv().is_err());
+        runner.commit().unwrap();
+
+        let event = rx.try_recv().unwrap();
+        assert_eq!(event.sighash, hex::encode([1u8; 32]));
+        assert_eq!(event.height, 1);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn rolled_back_ballots_have_no_abci_event() {
+        let pool = test_pool();
+        let (id, _) = open_election(&pool.get().unwrap(), "abci");
+        let mut runner = test_runner(&pool, broadcast::channel(16).0);
+
+        runner.begin_block().unwrap();
+        let included = exec_tx_result(runner.finalize_batch(&[ballot(&id, 1, &[1])]));
+        let rejected = exec_tx_result(runner.finalize_batch(&[ballot(&id, 2, &[1])]));
+        runner.commit().unwrap();
+
+        assert_eq!(included.code, 0);
+        assert_eq!(included.events.len(), 1);
+        let sighash = included.events[0]
+            .attributes
+            .iter()
+            .find(|a| a.key == "sighash")
+            .unwrap();
+        assert_eq!(sighash.value, hex::encode([1u8; 32]));
+        assert_eq!(rejected.code, ErrorCode::DoubleSpend.abci_code());
+        assert!(rejected.events.is_empty());
+    }
+
+    #[test]
+    fn rejections_are_committed_with_the_block() {
+        let pool = test_pool();
+        let (id, id_election) = open_election(&pool.get().unwrap(), "rejection");
+        let mut runner = test_runner(&pool, broadcast::channel(16).0);
+
+        runner.begin_block().unwrap();
+        runner.finalize_batch(&[ballot(&id, 1, &[1])]).unwrap();
+        // the second note is new but the first one is spent
+        assert!(runner.finalize_batch(&[ballot(&id, 2, &[2, 1])]).is_err());
+        runner.commit().unwrap();
+
+        let connection = pool.get().unwrap();
+        let status = get_ballot_status(&connection, id_election, &hex::encode([2u8; 32])).unwrap();
+        assert_eq!(status.status, SubmissionStatus::Rejected);
+        assert!(status.reason.unwrap().contains(&hex::encode([1u8; 32])));
+        assert!(is_spent(&connection, id_election, &[1u8; 32]).unwrap());
+        assert!(!is_spent(&connection, id_election, &[2u8; 32]).unwrap());
+        assert_eq!(get_num_ballots(&connection, id_election).unwrap(), 1);
+    }
+
+    fn is_double_spend(res: &Result<(), ErrorResponse>) -> bool {
+        matches!(res, Err(e) if e.code == ErrorCode::DoubleSpend)
+    }
+
+    #[test]
+    fn double_spends_fail_the_check_after_their_block() {
+        let pool = test_pool();
+        let (id, id_election) = open_election(&pool.get().unwrap(), "check");
+        let mut runner = test_runner(&pool, broadcast::channel(16).0);
+        let connection = pool.get().unwrap();
+        let spent = [1u8; 32];
+
+        check_unspent(&connection, id_election, [spent.as_slice()]).unwrap();
+        runner.begin_block().unwrap();
+        runner.finalize_batch(&[ballot(&id, 1, &[1])]).unwrap();
+        runner.commit().unwrap();
+
+        let res = check_unspent(&connection, id_election, [[2u8; 32].as_slice(), &spent]);
+        assert!(matches!(res, Err(VoteError::DoubleNullifier(dnf)) if dnf == hex::encode(spent)));
+        check_unspent(&connection, id_election, [[2u8; 32].as_slice()]).unwrap();
+    }
+
+    #[test]
+    fn double_spends_are_left_out_of_the_proposal() {
+        let pool = test_pool();
+        let (id, _) = open_election(&pool.get().unwrap(), "proposal");
+        let mut runner = test_runner(&pool, broadcast::channel(16).0);
+
+        // within the proposal, and within a ballot
+        let res = runner.prepare_proposal(&[
+            vec![ballot(&id, 1, &[1])],
+            vec![ballot(&id, 2, &[1])],
+            vec![ballot(&id, 3, &[3, 3])],
+            vec![ballot(&id, 4, &[4, 2])],
+        ]);
+        assert!(res[0].is_ok());
+        assert!(is_double_spend(&res[1]));
+        assert!(is_double_spend(&res[2]));
+        assert!(res[3].is_ok());
+
+        // a proposal that is not finalized leaves no spent note
+        let res = runner.prepare_proposal(&[vec![ballot(&id, 2, &[1])]]);
+        assert!(res[0].is_ok());
+
+        // across blocks
+        runner.begin_block().unwrap();
+        runner.finalize_batch(&[ballot(&id, 1, &[1])]).unwrap();
+        runner.commit().unwrap();
+        let res =
+            runner.prepare_proposal(&[vec![ballot(&id, 2, &[1])], vec![ballot(&id, 3, &[3])]]);
+        assert!(is_double_spend(&res[0]));
+        assert!(res[1].is_ok());
+    }
+
+    #[test]
+    fn double_spends_are_rolled_back_at_finalize() {
+        let pool = test_pool();
+        let (id, id_election) = open_election(&pool.get().unwrap(), "finalize");
+        let mut runner = test_runner(&pool, broadcast::channel(16).0);
+        let double_spend = |res: Result<Vec<BallotEvent>, ErrorResponse>| {
+            matches!(res, Err(e) if e.code == ErrorCode::DoubleSpend)
+        };
+
+        // within a block, and within a ballot
+        runner.begin_block().unwrap();
+        runner.finalize_batch(&[ballot(&id, 1, &[1])]).unwrap();
+        assert!(double_spend(runner.finalize_batch(&[ballot(&id, 2, &[1])])));
+        assert!(double_spend(runner.finalize_batch(&[ballot(&id, 3, &[3, 3])])));
+        runner.commit().unwrap();
+
+        // across blocks
+        runner.begin_block().unwrap();
+        assert!(double_spend(runner.finalize_batch(&[ballot(&id, 4, &[1])])));
+        let event = runner.finalize_batch(&[ballot(&id, 5, &[3])]).unwrap().remove(0);
+        runner.commit().unwrap();
+
+        let connection = pool.get().unwrap();
+        assert_eq!(event.height, 2);
+        assert_eq!(heights(&connection, id_election), [1, 2]);
+        assert!(is_spent(&connection, id_election, &[3u8; 32]).unwrap());
+    }
+
+    #[test]
+    fn chained_ballots_are_included_together() {
+        let pool = test_pool();
+        let connection = pool.get().unwrap();
+        let (id, id_election) = open_election(&connection, "chained");
+        let mut runner = test_runner(&pool, broadcast::channel(16).0);
+        let first = ballot(&id, 1, &[1]);
+        let mut second = ballot(&id, 2, &[2]);
+        second.anchor = root_after(&connection, id_election, &[&first]);
+
+        runner.begin_block().unwrap();
+        let events = runner.finalize_batch(&[first, second]).unwrap();
+        runner.commit().unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(heights(&connection, id_election), [1, 2]);
+        let block = exec_tx_result(Ok(events));
+        assert_eq!(block.events.len(), 2);
+    }
+
+    #[test]
+    fn batches_are_rejected_as_a_whole() {
+        let pool = test_pool();
+        let connection = pool.get().unwrap();
+        let (id, id_election) = open_election(&connection, "batch");
+        let mut runner = test_runner(&pool, broadcast::channel(16).0);
+        let first = ballot(&id, 1, &[1]);
+        let mut second = ballot(&id, 2, &[2]);
+        second.anchor = vec![0; 32];
+
+        runner.begin_block().unwrap();
+        let res = runner.finalize_batch(&[first, second]);
+        runner.commit().unwrap();
+
+        assert!(matches!(res, Err(e) if e.code == ErrorCode::BadRoot));
+        assert_eq!(get_num_ballots(&connection, id_election).unwrap(), 0);
+        assert!(!is_spent(&connection, id_election, &[1u8; 32]).unwrap());
+        for n in [1u8, 2] {
+            let status =
+                get_ballot_status(&connection, id_election, &hex::encode([n; 32])).unwrap();
+            assert_eq!(status.status, SubmissionStatus::Rejected);
+        }
+    }
+
+    #[test]
+    fn chained_ballots_fail_after_other_ballots() {
+        let pool = test_pool();
+        let connection = pool.get().unwrap();
+        let (id, id_election) = open_election(&connection, "tip");
+        let mut runner = test_runner(&pool, broadcast::channel(16).0);
+        let chained = || {
+            let first = ballot(&id, 1, &[1]);
+            let mut second = ballot(&id, 2, &[2]);
+            second.anchor = root_after(&connection, id_election, &[&first]);
+            vec![first, second]
+        };
+
+        let res = runner.prepare_proposal(&[chained()]);
+        assert!(res[0].is_ok());
+        // another ballot moves the tip before the batch
+        let res = runner.prepare_proposal(&[vec![ballot(&id, 3, &[3])], chained()]);
+        assert!(res[0].is_ok());
+        assert!(matches!(&res[1], Err(e) if e.code == ErrorCode::BadRoot));
+
+        runner.begin_block().unwrap();
+        runner.finalize_batch(&[ballot(&id, 3, &[3])]).unwrap();
+        let res = runner.finalize_batch(&chained());
+        runner.commit().unwrap();
+        assert!(matches!(res, Err(e) if e.code == ErrorCode::BadRoot));
+        assert_eq!(heights(&connection, id_election), [1]);
+    }
+}
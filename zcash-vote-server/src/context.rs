@@ -1,22 +1,64 @@
+use std::{sync::Arc, time::Duration};
+
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
+use rocket::tokio::sync::broadcast::{self, Sender};
+use zcash_vote::client::BallotEvent;
+
+use crate::{
+    admission::{Admission, VerifiedBallots},
+    sequencer::Sequencer,
+};
+
+/// Events not yet received by a slow subscriber are dropped
+/// after that many new events
+const EVENT_CAPACITY: usize = 1024;
 
 pub struct Context {
     pub data_path: String,
     pub db_path: String,
     pub comet_bft: u16,
+    /// Elections must be signed by this organizer key when set
+    pub organizer_pubkey: Option<String>,
     pub pool: Pool<SqliteConnectionManager>,
+    /// Committed ballots, of every election
+    pub events: Sender<BallotEvent>,
+    /// Limits the ballots posted to the REST API
+    pub admission: Admission,
+    /// Ballots verified by the REST API or by check_tx
+    pub verified: VerifiedBallots,
+    /// Longest wait for a ballot submitted in commit mode
+    pub commit_deadline: Duration,
+    /// Set in standalone mode, where ballots go to the in-process
+    /// sequencer instead of CometBFT
+    pub sequencer: Option<Arc<Sequencer>>,
 }
 
 impl Context {
-    pub fn new(data_path: String, db_path: String, comet_bft: u16) -> Self {
-        let pool = Pool::new(SqliteConnectionManager::file(&db_path)).unwrap();
+    pub fn new(
+        data_path: String,
+        db_path: String,
+        comet_bft: u16,
+        organizer_pubkey: Option<String>,
+        admission: Admission,
+        commit_deadline: Duration,
+    ) -> Self {
+        let manager = SqliteConnectionManager::file(&db_path)
+            .with_init(|c| c.execute_batch("PRAGMA foreign_keys = ON"));
+        let pool = Pool::new(manager).unwrap();
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
 
         Self {
             data_path,
             db_path,
             comet_bft,
+            organizer_pubkey,
             pool,
+            events,
+            admission,
+            verified: VerifiedBallots::default(),
+            commit_deadline,
+            sequencer: None,
         }
     }
 }
@@ -1,9 +1,15 @@
 use anyhow::Result;
 use blake2b_simd::Params;
-use orchard::vote::Ballot;
+use orchard::vote::{Ballot, Frontier};
 use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
-use zcash_vote::{db::{load_prop, store_cmx_root, store_prop}, election::Election};
+use zcash_vote::{
+    client::{BallotStatus, ElectionStatus, ElectionSummary, SubmissionStatus},
+    db::{load_prop, store_cmx_root, store_prop},
+    election::Election,
+    errors::{ErrorCode, ErrorResponse, VoteError},
+    migration::{migrate, Migration},
+};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AppState {
@@ -11,17 +17,31 @@ pub struct AppState {
     pub hash: String,
 }
 
+/// Property that holds the version of the server tables
+pub const SERVER_SCHEMA_VERSION: &str = "server_schema_version";
+
+/// Migrations of the server tables, applied after the base schema
+pub const SERVER_MIGRATIONS: &[Migration] = &[migration_001, migration_002, migration_003, migration_004];
+
 pub fn create_schema(connection: &Connection) -> Result<()> {
     zcash_vote::db::create_schema(connection)?;
+    migrate(connection, SERVER_SCHEMA_VERSION, SERVER_MIGRATIONS)?;
 
-    connection.execute(
-        "CREATE TABLE IF NOT EXISTS properties(
-        id_property INTEGER PRIMARY KEY,
-        name TEXT NOT NULL UNIQUE,
-        value TEXT NOT NULL)",
-        [],
-    )?;
+    if load_prop(connection, "state")?.is_none() {
+        let hash = Params::new().hash_length(32).personal(b"Zcash_Vote_CmBFT").to_state().finalize();
+        let hash = hex::encode(&hash.as_bytes());
+
+        let initial_state= AppState {
+            height: 0,
+            hash,
+        };
+        store_prop(connection, "state", &serde_json::to_string(&initial_state).unwrap())?;
+    }
+
+    Ok(())
+}
 
+fn migration_001(connection: &Connection) -> Result<()> {
     connection.execute(
         "CREATE TABLE IF NOT EXISTS elections(
             id_election INTEGER PRIMARY KEY,
@@ -30,18 +50,80 @@ pub fn create_schema(connection: &Connection) -> Result<()> {
             closed BOOLEAN NOT NULL)",
         [],
     )?;
+    Ok(())
+}
 
-    if load_prop(connection, "state")?.is_none() {
-        let hash = Params::new().hash_length(32).personal(b"Zcash_Vote_CmBFT").to_state().finalize();
-        let hash = hex::encode(&hash.as_bytes());
+/// Every table that refers to an election uses `elections.id_election`
+/// and is constrained by a foreign key
+fn migration_002(connection: &Connection) -> Result<()> {
+    connection.execute_batch(
+        "CREATE TABLE dnfs_new(
+        id_dnf INTEGER PRIMARY KEY NOT NULL,
+        election INTEGER NOT NULL REFERENCES elections(id_election),
+        hash BLOB NOT NULL,
+        CONSTRAINT u_dnfs UNIQUE (election, hash));
+        INSERT INTO dnfs_new(id_dnf, election, hash)
+        SELECT id_dnf, election, hash FROM dnfs;
+        DROP TABLE dnfs;
+        ALTER TABLE dnfs_new RENAME TO dnfs;
 
-        let initial_state= AppState {
-            height: 0,
-            hash,
-        };
-        store_prop(connection, "state", &serde_json::to_string(&initial_state).unwrap())?;
-    }
+        CREATE TABLE ballots_new(
+        id_ballot INTEGER PRIMARY KEY,
+        election INTEGER NOT NULL REFERENCES elections(id_election),
+        height INTEGER NOT NULL,
+        hash BLOB NOT NULL,
+        data BLOB NOT NULL,
+        CONSTRAINT u_ballots UNIQUE (election, hash));
+        INSERT INTO ballots_new(id_ballot, election, height, hash, data)
+        SELECT id_ballot, election, height, hash, data FROM ballots;
+        DROP TABLE ballots;
+        ALTER TABLE ballots_new RENAME TO ballots;
+
+        CREATE TABLE cmx_roots_new(
+        id_cmx_root INTEGER PRIMARY KEY,
+        election INTEGER NOT NULL REFERENCES elections(id_election),
+        height INTEGER NOT NULL,
+        hash BLOB NOT NULL,
+        CONSTRAINT u_cmx_roots UNIQUE (election, hash));
+        INSERT INTO cmx_roots_new(id_cmx_root, election, height, hash)
+        SELECT id_cmx_root, election, height, hash FROM cmx_roots;
+        DROP TABLE cmx_roots;
+        ALTER TABLE cmx_roots_new RENAME TO cmx_roots;
+
+        CREATE TABLE cmx_frontiers_new(
+        id_cmx_frontier INTEGER PRIMARY KEY,
+        election INTEGER NOT NULL REFERENCES elections(id_election),
+        height INTEGER NOT NULL,
+        frontier TEXT NOT NULL,
+        CONSTRAINT u_cmx_frontiers UNIQUE (election, height));
+        INSERT INTO cmx_frontiers_new(id_cmx_frontier, election, height, frontier)
+        SELECT id_cmx_frontier, election, height, frontier FROM cmx_frontiers;
+        DROP TABLE cmx_frontiers;
+        ALTER TABLE cmx_frontiers_new RENAME TO cmx_frontiers;",
+    )?;
+    Ok(())
+}
+
+/// Ballots posted to this server that are not finalized,
+/// either waiting for a block or rejected
+fn migration_003(connection: &Connection) -> Result<()> {
+    connection.execute(
+        "CREATE TABLE submissions(
+        id_submission INTEGER PRIMARY KEY,
+        election INTEGER NOT NULL REFERENCES elections(id_election),
+        hash BLOB NOT NULL,
+        status TEXT NOT NULL,
+        reason TEXT,
+        CONSTRAINT u_submissions UNIQUE (election, hash))",
+        [],
+    )?;
+    Ok(())
+}
 
+/// Error code of the rejected submissions, so that clients
+/// waiting for a ballot get the same error as the vote chain
+fn migration_004(connection: &Connection) -> Result<()> {
+    connection.execute("ALTER TABLE submissions ADD COLUMN code TEXT", [])?;
     Ok(())
 }
 
@@ -73,6 +155,92 @@ pub fn store_election(connection: &Connection, election: &Election, closed: bool
     Ok(id_election)
 }
 
+/// Start the commitment tree of an election at the frontier of its
+/// snapshot. Does nothing if the election has one already
+pub fn store_election_frontier(
+    connection: &Connection,
+    id_election: u32,
+    frontier: &Frontier,
+) -> Result<()> {
+    let cmx_root = frontier.root();
+    connection.execute(
+        "INSERT INTO cmx_frontiers(election, height, frontier)
+        VALUES (?1, 0, ?2) ON CONFLICT DO NOTHING",
+        params![id_election, serde_json::to_string(frontier)?],
+    )?;
+    connection.execute(
+        "INSERT INTO cmx_roots(election, height, hash)
+        VALUES (?1, 0, ?2) ON CONFLICT DO NOTHING",
+        params![id_election, &cmx_root],
+    )?;
+    Ok(())
+}
+
+/// Frontier of the commitment tree after the last ballot
+/// of the election, with the number of ballots it has
+pub fn get_last_frontier(connection: &Connection, id_election: u32) -> Result<(u32, Frontier)> {
+    let (height, frontier) = connection.query_row(
+        "SELECT height, frontier FROM cmx_frontiers WHERE election = ?1
+        ORDER BY height DESC LIMIT 1",
+        [id_election],
+        |r| Ok((r.get::<_, u32>(0)?, r.get::<_, String>(1)?)),
+    )?;
+    Ok((height, serde_json::from_str(&frontier)?))
+}
+
+pub fn list_elections(connection: &Connection) -> Result<Vec<ElectionSummary>> {
+    let mut s = connection.prepare(
+        "SELECT e.id, e.definition, e.closed, COUNT(b.id_ballot)
+        FROM elections e LEFT JOIN ballots b ON b.election = e.id_election
+        GROUP BY e.id_election ORDER BY e.id_election",
+    )?;
+    let rows = s.query_map([], |r| {
+        Ok((
+            r.get::<_, String>(0)?,
+            r.get::<_, String>(1)?,
+            r.get::<_, bool>(2)?,
+            r.get::<_, u32>(3)?,
+        ))
+    })?;
+    let mut elections = vec![];
+    for r in rows {
+        let (id, definition, closed, num_ballots) = r?;
+        let election: Election = serde_json::from_str(&definition)?;
+        elections.push(ElectionSummary {
+            id,
+            name: election.name,
+            start_height: election.start_height,
+            end_height: election.end_height,
+            closed,
+            num_ballots,
+            parent: election.parent.map(|p| p.id),
+        });
+    }
+    Ok(elections)
+}
+
+pub fn get_election_status(connection: &Connection, id: &str) -> Result<ElectionStatus> {
+    let (id_election, _, closed) = get_election(connection, id)?;
+    let num_ballots = get_num_ballots(connection, id_election)?;
+    let cmx_root = connection.query_row(
+        "SELECT hash FROM cmx_roots WHERE election = ?1
+        ORDER BY height DESC LIMIT 1",
+        [id_election],
+        |r| r.get::<_, Vec<u8>>(0),
+    )?;
+    let app_state = load_prop(connection, "state")?
+        .ok_or(VoteError::NotFound("app state".to_string()))?;
+    let app_state: AppState = serde_json::from_str(&app_state)?;
+    Ok(ElectionStatus {
+        id: id.to_string(),
+        closed,
+        num_ballots,
+        cmx_root: hex::encode(cmx_root),
+        height: app_state.height,
+        app_hash: app_state.hash,
+    })
+}
+
 pub fn check_cmx_root(connection: &Connection, id_election: u32, cmx: &[u8]) -> Result<()> {
     let r = connection
         .query_row(
@@ -81,22 +249,38 @@ pub fn check_cmx_root(connection: &Connection, id_election: u32, cmx: &[u8]) ->
             |_| Ok(()),
         )
         .optional()?;
-    r.ok_or(anyhow::anyhow!("Invalid cmx root"))
+    r.ok_or(VoteError::BadRoot("cmx".to_string()).into())
+}
+
+/// Check if a domain nullifier was used by a finalized ballot
+/// of the election
+pub fn is_spent(connection: &Connection, id_election: u32, dnf: &[u8]) -> Result<bool> {
+    let spent = connection
+        .query_row(
+            "SELECT 1 FROM dnfs WHERE election = ?1 AND hash = ?2",
+            params![id_election, dnf],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    Ok(spent)
 }
 
+/// Store the JSON `data` of a ballot, with the root of the
+/// commitment tree after it
 pub fn store_ballot(
     connection: &Connection,
     id_election: u32,
     height: u32,
-    ballot: &Ballot,
+    sighash: &[u8],
+    data: &str,
     cmx_root: &[u8],
 ) -> Result<u32> {
-    let hash = ballot.data.sighash()?;
     connection.execute(
         "INSERT INTO ballots
         (election, height, hash, data)
         VALUES (?1, ?2, ?3, ?4)",
-        params![id_election, height, &hash, serde_json::to_string(ballot)?],
+        params![id_election, height, sighash, data],
     )?;
     let id_ballot = connection.last_insert_rowid() as u32;
 
@@ -113,6 +297,25 @@ pub fn get_ballot_height(connection: &Connection, id_election: u32, height: u32)
     Ok(e)
 }
 
+/// Ballots from height `start` up to `end` inclusive, at most `limit`
+pub fn get_ballot_range(
+    connection: &Connection,
+    id_election: u32,
+    start: u32,
+    end: u32,
+    limit: u32,
+) -> Result<Vec<(u32, String)>> {
+    let mut s = connection.prepare(
+        "SELECT height, data FROM ballots
+        WHERE election = ?1 AND height >= ?2 AND height <= ?3
+        ORDER BY height LIMIT ?4",
+    )?;
+    let rows = s.query_map(params![id_election, start, end, limit], |r| {
+        Ok((r.get::<_, u32>(0)?, r.get::<_, String>(1)?))
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
 pub fn get_num_ballots(connection: &Connection, id_election: u32) -> Result<u32> {
     let n = connection.query_row(
         "SELECT COUNT(*) FROM ballots WHERE election = ?1",
@@ -121,3 +324,232 @@ pub fn get_num_ballots(connection: &Connection, id_election: u32) -> Result<u32>
     )?;
     Ok(n)
 }
+
+/// Record the outcome of the submission of a ballot,
+/// with the error that rejected it
+pub fn store_submission(
+    connection: &Connection,
+    id: &str,
+    sighash: &[u8],
+    status: SubmissionStatus,
+    error: Option<&ErrorResponse>,
+) -> Result<()> {
+    let (id_election, _, _) = get_election(connection, id)?;
+    let status = serde_json::to_value(status)?;
+    let code = error.map(|e| serde_json::to_value(e.code)).transpose()?;
+    connection.execute(
+        "INSERT INTO submissions(election, hash, status, reason, code)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT DO UPDATE SET
+        status = excluded.status,
+        reason = excluded.reason,
+        code = excluded.code",
+        params![
+            id_election,
+            sighash,
+            status.as_str(),
+            error.map(|e| &e.message),
+            code.as_ref().and_then(|c| c.as_str())
+        ],
+    )?;
+    Ok(())
+}
+
+/// Error of a rejected submission. Submissions rejected before
+/// their error code was recorded are internal errors
+pub fn get_rejection(
+    connection: &Connection,
+    id_election: u32,
+    sighash: &[u8],
+) -> Result<Option<ErrorResponse>> {
+    let rejected = serde_json::to_value(SubmissionStatus::Rejected)?;
+    let rejection = connection
+        .query_row(
+            "SELECT reason, code FROM submissions
+            WHERE election = ?1 AND hash = ?2 AND status = ?3",
+            params![id_election, sighash, rejected.as_str()],
+            |r| Ok((r.get::<_, Option<String>>(0)?, r.get::<_, Option<String>>(1)?)),
+        )
+        .optional()?;
+    let Some((reason, code)) = rejection else {
+        return Ok(None);
+    };
+    let code = match code {
+        Some(code) => serde_json::from_value(code.into())?,
+        None => ErrorCode::Internal,
+    };
+    Ok(Some(ErrorResponse {
+        code,
+        message: reason.unwrap_or_default(),
+    }))
+}
+
+/// Finalized ballots are included, the others have the status
+/// of their submission
+pub fn get_ballot_status(
+    connection: &Connection,
+    id_election: u32,
+    sighash: &str,
+) -> Result<BallotStatus> {
+    let hash = hex::decode(sighash).map_err(|_| VoteError::InvalidBallot(format!("sighash {sighash}")))?;
+    // the cmx root of a ballot is stored at the id of the ballot
+    let included = connection
+        .query_row(
+            "SELECT b.height, b.data, r.hash FROM ballots b
+            JOIN cmx_roots r ON r.election = b.election AND r.height = b.id_ballot
+            WHERE b.election = ?1 AND b.hash = ?2",
+            params![id_election, &hash],
+            |r| {
+                Ok((
+                    r.get::<_, u32>(0)?,
+                    r.get::<_, String>(1)?,
+                    r.get::<_, Vec<u8>>(2)?,
+                ))
+            },
+        )
+        .optional()?;
+    if let Some((height, data, cmx_root)) = included {
+        let ballot: Ballot = serde_json::from_str(&data)?;
+        return Ok(BallotStatus {
+            sighash: sighash.to_string(),
+            status: SubmissionStatus::Included,
+            height: Some(height),
+            reason: None,
+            cmx_root: Some(hex::encode(cmx_root)),
+            dnfs: ballot.data.actions.iter().map(|a| hex::encode(&a.nf)).collect(),
+        });
+    }
+    let (status, reason) = connection
+        .query_row(
+            "SELECT status, reason FROM submissions WHERE election = ?1 AND hash = ?2",
+            params![id_election, &hash],
+            |r| Ok((r.get::<_, String>(0)?, r.get::<_, Option<String>>(1)?)),
+        )
+        .optional()?
+        .ok_or(VoteError::NotFound(format!("ballot {sighash}")))?;
+    Ok(BallotStatus {
+        sighash: sighash.to_string(),
+        status: serde_json::from_value(status.into())?,
+        height: None,
+        reason,
+        cmx_root: None,
+        dnfs: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_vote::{
+        db::MIGRATIONS,
+        election::ParentElection,
+        migration::{get_schema_version, BASELINE_SCHEMA, SCHEMA_VERSION},
+    };
+
+    use super::*;
+    use crate::testing::{open_election, test_pool};
+
+    /// Tables of the server databases created before the migrations
+    const BASELINE_SERVER_SCHEMA: &str = "
+        CREATE TABLE elections(
+        id_election INTEGER PRIMARY KEY,
+        id TEXT NOT NULL UNIQUE,
+        definition TEXT NOT NULL,
+        closed BOOLEAN NOT NULL);";
+
+    #[test]
+    fn baseline_database_migrates_to_head() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(BASELINE_SCHEMA).unwrap();
+        connection.execute_batch(BASELINE_SERVER_SCHEMA).unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO elections(id_election, id, definition, closed)
+                VALUES (1, 'e1', '{}', FALSE);
+                INSERT INTO ballots(election, height, hash, data) VALUES (1, 1, x'01', '{}');
+                INSERT INTO dnfs(election, hash) VALUES (1, x'03');
+                INSERT INTO cmx_roots(election, height, hash) VALUES (1, 0, x'05');
+                INSERT INTO cmx_frontiers(election, height, frontier) VALUES (1, 0, '{}');
+                INSERT INTO properties(name, value) VALUES ('state', '{\"height\":5,\"hash\":\"00\"}');
+                INSERT INTO notes(election, position, height, txid, value,
+                div, rseed, nf, dnf, rho, spent)
+                VALUES (1, 7, 100, x'06', 1000, x'07', x'08', x'02', x'03', x'09', NULL);",
+            )
+            .unwrap();
+
+        create_schema(&connection).unwrap();
+
+        assert_eq!(
+            get_schema_version(&connection, SCHEMA_VERSION).unwrap() as usize,
+            MIGRATIONS.len()
+        );
+        assert_eq!(
+            get_schema_version(&connection, SERVER_SCHEMA_VERSION).unwrap() as usize,
+            SERVER_MIGRATIONS.len()
+        );
+        let (id_election, _, closed) = get_election(&connection, "e1").unwrap();
+        assert_eq!(id_election, 1);
+        assert!(!closed);
+        assert_eq!(get_num_ballots(&connection, 1).unwrap(), 1);
+        assert!(is_spent(&connection, 1, &[3]).unwrap());
+        check_cmx_root(&connection, 1, &[5]).unwrap();
+        // the chain state is kept
+        let state: AppState =
+            serde_json::from_str(&load_prop(&connection, "state").unwrap().unwrap()).unwrap();
+        assert_eq!(state.height, 5);
+        let (account, value) = connection
+            .query_row("SELECT account, value FROM notes", [], |r| {
+                Ok((r.get::<_, u32>(0)?, r.get::<_, u64>(1)?))
+            })
+            .unwrap();
+        assert_eq!((account, value), (0, 1000));
+
+        // the tables added by the migrations are usable
+        let rejection = ErrorResponse {
+            code: ErrorCode::DoubleSpend,
+            message: "spent".to_string(),
+        };
+        store_submission(&connection, "e1", &[2], SubmissionStatus::Rejected, Some(&rejection))
+            .unwrap();
+        let stored = get_rejection(&connection, 1, &[2]).unwrap().unwrap();
+        assert_eq!(stored.code, ErrorCode::DoubleSpend);
+        assert_eq!(stored.message, "spent");
+
+        // election keys are checked
+        connection.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+        assert!(zcash_vote::db::store_dnf(&connection, 2, &[4]).is_err());
+    }
+
+    #[test]
+    fn elections_are_listed_with_their_status() {
+        let pool = test_pool();
+        let connection = pool.get().unwrap();
+        let (first, id_first) = open_election(&connection, "first");
+        let question = Election {
+            name: "question".to_string(),
+            parent: Some(ParentElection {
+                id: first.clone(),
+                index: 0,
+            }),
+            ..Election::default()
+        };
+        store_election(&connection, &question, true).unwrap();
+        store_ballot(&connection, id_first, 1, &[1; 32], "{}", &[7; 32]).unwrap();
+        store_prop(&connection, "state", r#"{"height":3,"hash":"ab"}"#).unwrap();
+
+        let elections = list_elections(&connection).unwrap();
+        assert_eq!(elections.len(), 2);
+        let (e, q) = (&elections[0], &elections[1]);
+        assert_eq!((e.id.as_str(), e.name.as_str()), (first.as_str(), "first"));
+        assert_eq!((e.closed, e.num_ballots), (false, 1));
+        assert!(e.parent.is_none());
+        assert_eq!((q.id.clone(), q.name.as_str()), (question.id(), "question"));
+        assert_eq!((q.closed, q.num_ballots), (true, 0));
+        assert_eq!(q.parent.as_deref(), Some(first.as_str()));
+
+        let status = get_election_status(&connection, &first).unwrap();
+        assert_eq!((status.closed, status.num_ballots), (false, 1));
+        assert_eq!(status.cmx_root, hex::encode([7u8; 32]));
+        assert_eq!((status.height, status.app_hash.as_str()), (3, "ab"));
+        assert!(get_election_status(&connection, "unknown").is_err());
+    }
+}
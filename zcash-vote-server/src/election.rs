@@ -4,9 +4,11 @@ use std::{
 };
 
 use anyhow::Result;
-use zcash_vote::election::Election;
+use zcash_vote::{election::Election, organizer::verify_election};
 
-pub fn scan_data_dir(data_dir: &str) -> Result<Vec<Election>> {
+/// Load the valid elections of the data directory. When an organizer key
+/// is given, elections that it did not sign are skipped
+pub fn scan_data_dir(data_dir: &str, organizer_pubkey: Option<&str>) -> Result<Vec<Election>> {
     let mut elections = vec![];
     let dir = read_dir(data_dir)?.flatten(); // Simplify the iterator
 
@@ -16,6 +18,13 @@ pub fn scan_data_dir(data_dir: &str) -> Result<Vec<Election>> {
             if let Ok(election) =
                 serde_json::from_reader::<_, Election>(BufReader::new(File::open(&p)?))
             {
+                if let Err(e) = election
+                    .validate()
+                    .and_then(|_| verify_election(&election, organizer_pubkey))
+                {
+                    tracing::error!("Skipping {}: {}", p.display(), e);
+                    continue;
+                }
                 tracing::info!("Election ID: {}", election.id());
                 elections.push(election);
             }
@@ -1,5 +1,10 @@
+pub mod admission;
 pub mod context;
 pub mod db;
 pub mod election;
+pub mod openapi;
 pub mod routes;
+pub mod sequencer;
+#[cfg(test)]
+mod testing;
 pub mod chain;
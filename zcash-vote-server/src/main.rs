@@ -1,55 +1,79 @@
 use anyhow::{Error, Result};
-use rocket::{figment::Figment, routes, Build, Config, Rocket, State};
+use rocket::{figment::Figment, Build, Config, Rocket};
 use rocket_cors::CorsOptions;
-use rusqlite::params;
+use std::{sync::Arc, time::Duration};
 use tendermint_abci::ServerBuilder;
 use zcash_vote_server::{
+    admission::Admission,
     chain::VoteChain,
     context::Context,
-    db::{create_schema, store_election},
+    db::{create_schema, store_election, store_election_frontier},
     election::scan_data_dir,
-    routes::{get_ballot_height, get_election_by_id, get_num_ballots, post_ballot},
+    openapi::api_routes,
+    sequencer::Sequencer,
 };
-
-#[rocket::get("/")]
-fn index(context: &State<Context>) -> Result<String, String> {
-    let r = || {
-        let connection = context.pool.get()?;
-        let n = connection.query_row("SELECT COUNT(*) FROM t", [], |r| r.get::<_, u32>(0))?;
-        Ok::<_, Error>(n.to_string())
-    };
-    r().map_err(|e| e.to_string())
-}
+use zcash_vote::organizer::verifying_key;
 
 pub fn init_context(config: &Figment) -> Result<Context> {
     let data_path: String = config.extract_inner("custom.data_path")?;
     let db_path: String = config.extract_inner("custom.db_path")?;
     let cometbft_port: u16 = config.extract_inner("custom.cometbft_port")?;
-    let context = Context::new(data_path, db_path, cometbft_port);
+    // an empty key, like the commented one of Rocket.toml, is no key
+    let organizer_pubkey: Option<String> = config
+        .extract_inner("custom.organizer_pubkey")
+        .ok()
+        .filter(|k: &String| !k.trim().is_empty());
+    if let Some(key) = organizer_pubkey.as_deref() {
+        verifying_key(key)?;
+    }
+    let ballots_per_minute: u32 = config
+        .extract_inner("custom.ballots_per_minute")
+        .unwrap_or(30);
+    let max_pending_ballots: usize = config
+        .extract_inner("custom.max_pending_ballots")
+        .unwrap_or(16);
+    // X-Real-IP is set by the clients unless a proxy replaces it
+    let trusted_proxy: bool = config
+        .extract_inner("custom.trusted_proxy")
+        .unwrap_or(false);
+    let admission = Admission::new(ballots_per_minute, max_pending_ballots, trusted_proxy);
+    let commit_deadline: u64 = config
+        .extract_inner("custom.commit_deadline")
+        .unwrap_or(20);
+    let context = Context::new(
+        data_path,
+        db_path,
+        cometbft_port,
+        organizer_pubkey,
+        admission,
+        Duration::from_secs(commit_deadline),
+    );
     Ok(context)
 }
 
 async fn rocket_build(config: Figment, context: Context) -> Rocket<Build> {
     let init = async {
-        let elections = scan_data_dir(&context.data_path)?;
+        let elections = scan_data_dir(&context.data_path, context.organizer_pubkey.as_deref())?;
         tracing::info!("# elections = {}", elections.len());
         let connection = context.pool.get()?;
         connection.execute("UPDATE elections SET closed = TRUE", [])?;
         for e in elections.iter() {
             let connection = context.pool.get()?;
-            let id_election = store_election(&connection, e, false)?;
-            let cmx_root = e.cmx_frontier.as_ref().unwrap().root();
-            let frontier = serde_json::to_string(&e.cmx_frontier)?;
-            connection.execute(
-                "INSERT INTO cmx_frontiers(election, height, frontier)
-            VALUES (?1, 0, ?2) ON CONFLICT DO NOTHING",
-                params![id_election, &frontier],
-            )?;
-            connection.execute(
-                "INSERT INTO cmx_roots(election, height, hash)
-            VALUES (?1, 0, ?2) ON CONFLICT DO NOTHING",
-                params![id_election, &cmx_root],
-            )?;
+            if !e.questions.is_empty() {
+                // published for the clients, but ballots are cast
+                // on the election of each question
+                store_election(&connection, e, true)?;
+            }
+        }
+        let elections = elections.iter().flat_map(|e| e.question_elections());
+        for e in elections {
+            let connection = context.pool.get()?;
+            let id_election = store_election(&connection, &e, false)?;
+            let frontier = e
+                .cmx_frontier
+                .as_ref()
+                .ok_or(anyhow::anyhow!("Missing cmx frontier"))?;
+            store_election_frontier(&connection, id_election, frontier)?;
         }
 
         Ok::<_, Error>(context)
@@ -58,16 +82,10 @@ async fn rocket_build(config: Figment, context: Context) -> Rocket<Build> {
 
     let cors = CorsOptions::default().to_cors().unwrap();
 
-    rocket::custom(config).attach(cors).manage(context).mount(
-        "/",
-        routes![
-            index,
-            get_election_by_id,
-            post_ballot,
-            get_num_ballots,
-            get_ballot_height
-        ],
-    )
+    rocket::custom(config)
+        .attach(cors)
+        .manage(context)
+        .mount("/", api_routes())
 }
 
 #[rocket::main]
@@ -79,21 +97,34 @@ pub async fn main() {
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
     let config = Config::figment();
-    let context = init_context(&config).unwrap();
+    let mut context = init_context(&config).unwrap();
     {
         let connection = context.pool.get().unwrap();
         create_schema(&connection).unwrap();
     }
 
-    let (app, runner) = VoteChain::new(context.pool.get().unwrap());
-    let server = ServerBuilder::new(1_000_000)
-        .bind(format!("{}:{}", "127.0.0.1", context.comet_bft), app)
-        .unwrap();
+    let (app, runner) = VoteChain::new(
+        context.pool.get().unwrap(),
+        context.events.clone(),
+        context.verified.clone(),
+    );
     std::thread::spawn(move || {
         let res = runner.run();
         println!("{:?}", res);
     });
-    std::thread::spawn(move || server.listen().unwrap());
+    let standalone: bool = config.extract_inner("custom.standalone").unwrap_or(false);
+    if standalone {
+        let block_time: u64 = config.extract_inner("custom.block_time").unwrap_or(1);
+        tracing::info!("Standalone mode, one block every {block_time}s");
+        let sequencer = Arc::new(Sequencer::new(app, Duration::from_secs(block_time)));
+        context.sequencer = Some(sequencer.clone());
+        std::thread::spawn(move || sequencer.run());
+    } else {
+        let server = ServerBuilder::new(1_000_000)
+            .bind(format!("{}:{}", "127.0.0.1", context.comet_bft), app)
+            .unwrap();
+        std::thread::spawn(move || server.listen().unwrap());
+    }
 
     rocket_build(config, context).await.launch().await.unwrap();
 }
@@ -0,0 +1,113 @@
+use rocket::{http::ContentType, Route};
+use utoipa::OpenApi;
+use zcash_vote::{
+    client::{BallotEvent, BallotStatus, ElectionStatus, ElectionSummary, SubmissionStatus},
+    election::{CandidateChoice, Election, ParentElection, Question, SnapshotMode},
+    errors::{ErrorCode, ErrorResponse},
+    network::Network,
+    organizer::ElectionSignature,
+};
+
+use crate::routes;
+
+/// Description of the REST API. Its version is the version of the
+/// server crate
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Zcash Vote Server"),
+    paths(
+        routes::index,
+        routes::list_elections,
+        routes::get_election_by_id,
+        routes::get_election_status,
+        routes::get_num_ballots,
+        routes::get_ballot_height,
+        routes::get_ballot_range,
+        routes::get_ballot_status,
+        routes::post_ballot,
+        routes::post_ballot_commit,
+        routes::post_ballots_commit,
+        routes::ballot_events,
+    ),
+    components(schemas(
+        BallotEvent,
+        BallotStatus,
+        CandidateChoice,
+        Election,
+        ElectionSignature,
+        ElectionStatus,
+        ElectionSummary,
+        ErrorCode,
+        ErrorResponse,
+        Network,
+        ParentElection,
+        Question,
+        SnapshotMode,
+        SubmissionStatus,
+    ))
+)]
+pub struct ApiDoc;
+
+#[rocket::get("/openapi.json")]
+pub fn openapi() -> (ContentType, String) {
+    (ContentType::JSON, ApiDoc::openapi().to_pretty_json().unwrap())
+}
+
+/// Routes of the REST API, and the route of its description
+pub fn api_routes() -> Vec<Route> {
+    rocket::routes![
+        routes::index,
+        routes::list_elections,
+        routes::get_election_by_id,
+        routes::get_election_status,
+        routes::post_ballot,
+        routes::post_ballot_commit,
+        routes::post_ballots_commit,
+        routes::get_num_ballots,
+        routes::get_ballot_height,
+        routes::get_ballot_range,
+        routes::get_ballot_status,
+        routes::ballot_events,
+        openapi,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn every_route_is_described() {
+        let doc: Value = serde_json::from_str(&ApiDoc::openapi().to_json().unwrap()).unwrap();
+        let paths = doc["paths"].as_object().unwrap();
+        let mut described = 0;
+        for route in api_routes() {
+            let path = route.uri.path().replace('<', "{").replace('>', "}");
+            if path == "/openapi.json" {
+                continue;
+            }
+            let method = route.method.as_str().to_lowercase();
+            assert!(
+                paths.get(&path).and_then(|p| p.get(&method)).is_some(),
+                "{method} {path}"
+            );
+            described += 1;
+        }
+        let operations = paths
+            .values()
+            .map(|p| p.as_object().unwrap().len())
+            .sum::<usize>();
+        assert_eq!(described, operations);
+    }
+
+    #[test]
+    fn schemas_are_included() {
+        let doc: Value = serde_json::from_str(&ApiDoc::openapi().to_json().unwrap()).unwrap();
+        let schemas = doc["components"]["schemas"].as_object().unwrap();
+        for name in ["Election", "BallotStatus", "ErrorResponse", "ErrorCode"] {
+            assert!(schemas.contains_key(name), "{name}");
+        }
+    }
+}
@@ -1,11 +1,40 @@
 use anyhow::Error;
-use base64::{prelude::BASE64_STANDARD, Engine as _};
 use orchard::vote::Ballot;
-use rocket::{http::Status, response::status::Custom, serde::json::Json, State};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rocket::{
+    http::{Header, Status},
+    response::{
+        status::Custom,
+        stream::{Event, EventStream},
+    },
+    serde::json::Json,
+    tokio::{
+        select,
+        sync::broadcast::{error::RecvError, Receiver},
+        task::spawn_blocking,
+        time::{sleep, sleep_until, Instant},
+    },
+    Responder, Shutdown, State,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::time::Duration;
 
-use crate::{context::Context, db::get_election};
+use zcash_vote::{
+    client::{
+        BallotEvent, BallotStatus, CometRpc, ElectionStatus, ElectionSummary, SubmissionStatus,
+        MAX_BALLOTS_PER_PAGE,
+    },
+    election::Election,
+    errors::{ErrorResponse, VoteError},
+};
+
+use crate::{
+    admission::{check_batch, ClientAddress},
+    context::Context,
+    db::{get_election, get_rejection, store_submission},
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct Tx {
@@ -13,23 +42,150 @@ pub struct Tx {
     pub ballot: Ballot,
 }
 
+/// Ballots of an allocation, included together or not at all.
+/// Every ballot after the first is anchored on the commitment root
+/// after the ballot before it
+#[derive(Serialize, Deserialize)]
+pub struct BatchTx {
+    pub id: String,
+    pub ballots: Vec<Ballot>,
+}
+
+/// Prefix of the transactions of several ballots, the others are a `Tx`
+const BATCH_TX: &[u8] = b"BATCH";
+
+impl BatchTx {
+    /// A single ballot is encoded as a `Tx`, like before batches
+    pub fn encode(self) -> Vec<u8> {
+        let BatchTx { id, mut ballots } = self;
+        if ballots.len() == 1 {
+            let ballot = ballots.remove(0);
+            return bincode::serialize(&Tx { id, ballot }).unwrap();
+        }
+        let mut tx = BATCH_TX.to_vec();
+        tx.extend(bincode::serialize(&BatchTx { id, ballots }).unwrap());
+        tx
+    }
+
+    pub fn decode(tx: &[u8]) -> Result<Self, bincode::Error> {
+        match tx.strip_prefix(BATCH_TX) {
+            Some(batch) => bincode::deserialize(batch),
+            None => {
+                let Tx { id, ballot } = bincode::deserialize(tx)?;
+                Ok(BatchTx {
+                    id,
+                    ballots: vec![ballot],
+                })
+            }
+        }
+    }
+}
+
+/// Error body `{ "code": ..., "message": ... }` with the HTTP status
+/// that matches its code
+pub type ApiError = Custom<Json<VoteError>>;
+
+pub fn api_error(e: impl Into<VoteError>) -> ApiError {
+    let e: VoteError = e.into();
+    let status = Status::from_code(e.code().http_status()).unwrap_or(Status::InternalServerError);
+    Custom(status, Json(e))
+}
+
+#[utoipa::path(
+    get,
+    path = "/",
+    responses(
+        (status = 200, description = "Number of registered elections", body = u32, content_type = "text/plain"),
+    )
+)]
+#[rocket::get("/")]
+pub fn index(state: &State<Context>) -> Result<String, ApiError> {
+    (|| {
+        let connection = state.pool.get()?;
+        let n =
+            connection.query_row("SELECT COUNT(*) FROM elections", [], |r| r.get::<_, u32>(0))?;
+        Ok::<_, Error>(n.to_string())
+    })()
+    .map_err(api_error)
+}
+
+#[utoipa::path(
+    get,
+    path = "/elections",
+    responses(
+        (status = 200, description = "Elections registered on the server", body = [ElectionSummary]),
+    )
+)]
+#[rocket::get("/elections")]
+pub fn list_elections(state: &State<Context>) -> Result<Json<Vec<ElectionSummary>>, ApiError> {
+    (|| {
+        let connection = state.pool.get()?;
+        let elections = crate::db::list_elections(&connection)?;
+        Ok::<_, Error>(Json(elections))
+    })()
+    .map_err(api_error)
+}
+
+#[utoipa::path(
+    get,
+    path = "/election/{id}/status",
+    params(("id" = String, Path, description = "Election id")),
+    responses(
+        (status = 200, description = "State of the election on the vote chain", body = ElectionStatus),
+        (status = 404, description = "Unknown election", body = ErrorResponse),
+    )
+)]
+#[rocket::get("/election/<id>/status")]
+pub fn get_election_status(
+    id: String,
+    state: &State<Context>,
+) -> Result<Json<ElectionStatus>, ApiError> {
+    (|| {
+        let connection = state.pool.get()?;
+        let status = crate::db::get_election_status(&connection, &id)?;
+        Ok::<_, Error>(Json(status))
+    })()
+    .map_err(api_error)
+}
+
+#[utoipa::path(
+    get,
+    path = "/election/{id}",
+    params(("id" = String, Path, description = "Election id")),
+    responses(
+        (status = 200, description = "Election definition", body = Election),
+        (status = 404, description = "Unknown election", body = ErrorResponse),
+    )
+)]
 #[rocket::get("/election/<id>")]
-pub fn get_election_by_id(id: String, state: &State<Context>) -> Result<Json<Value>, String> {
+pub fn get_election_by_id(id: String, state: &State<Context>) -> Result<Json<Value>, ApiError> {
     (|| {
         let connection = state.pool.get()?;
         let (_, election, _) = get_election(&connection, &id)?;
         let election = serde_json::from_str::<Value>(&election)?;
         Ok::<_, Error>(Json(election))
     })()
-    .map_err(|e| e.to_string())
+    .map_err(api_error)
 }
 
+#[utoipa::path(
+    get,
+    path = "/election/{id}/ballot/height/{height}",
+    params(
+        ("id" = String, Path, description = "Election id"),
+        ("height" = u32, Path, description = "Height of the ballot, starting at 1"),
+    ),
+    responses(
+        (status = 200, description = "Ballot", body = Object),
+        (status = 404, description = "Unknown election or height", body = ErrorResponse),
+    )
+)]
 #[rocket::get("/election/<id>/ballot/height/<height>")]
 pub fn get_ballot_height(
     id: String,
     height: u32,
     state: &State<Context>,
-) -> Result<Json<Value>, Custom<String>> {
+) -> Result<Json<Value>, ApiError> {
     (|| {
         let connection = state.pool.get()?;
         let (id_election, _, _) = get_election(&connection, &id)?;
@@ -37,56 +193,412 @@ pub fn get_ballot_height(
         let ballot = serde_json::from_str::<Value>(&ballot)?;
         Ok::<_, Error>(Json(ballot))
     })()
-    .map_err(|e| Custom(Status::InternalServerError, e.to_string()))
+    .map_err(api_error)
+}
+
+/// Ballots as newline delimited JSON, in height order. Every line
+/// is `{"height": ..., "ballot": ...}`
+///
+/// `X-Last-Height` has the height of the last ballot, or `start - 1`
+/// when there is none
+#[derive(Responder)]
+#[response(content_type = "application/x-ndjson")]
+pub struct BallotRange {
+    body: String,
+    last_height: Header<'static>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/election/{id}/ballots",
+    params(
+        ("id" = String, Path, description = "Election id"),
+        ("start" = Option<u32>, Query, description = "First height, 1 by default"),
+        ("end" = Option<u32>, Query, description = "Last height, inclusive"),
+    ),
+    responses(
+        (status = 200, description = "Ballots with their heights as newline delimited JSON, \
+            at most 500", body = String, content_type = "application/x-ndjson",
+            headers(
+                ("X-Last-Height" = u32, description = "Height of the last ballot returned"),
+            )),
+        (status = 404, description = "Unknown election", body = ErrorResponse),
+    )
+)]
+#[rocket::get("/election/<id>/ballots?<start>&<end>")]
+pub fn get_ballot_range(
+    id: String,
+    start: Option<u32>,
+    end: Option<u32>,
+    state: &State<Context>,
+) -> Result<BallotRange, ApiError> {
+    (|| {
+        let start = start.unwrap_or(1).max(1);
+        let end = end.unwrap_or(u32::MAX);
+        let connection = state.pool.get()?;
+        let (id_election, _, _) = get_election(&connection, &id)?;
+        let ballots = crate::db::get_ballot_range(
+            &connection,
+            id_election,
+            start,
+            end,
+            MAX_BALLOTS_PER_PAGE,
+        )?;
+        let last_height = ballots.last().map(|(h, _)| *h).unwrap_or(start - 1);
+        let mut body = String::new();
+        // the ballots are stored as JSON
+        for (height, ballot) in ballots.iter() {
+            body.push_str(&format!("{{\"height\":{height},\"ballot\":{ballot}}}\n"));
+        }
+        Ok::<_, Error>(BallotRange {
+            body,
+            last_height: Header::new("X-Last-Height", last_height.to_string()),
+        })
+    })()
+    .map_err(api_error)
 }
 
+#[utoipa::path(
+    get,
+    path = "/election/{id}/ballot/{sighash}",
+    params(
+        ("id" = String, Path, description = "Election id"),
+        ("sighash" = String, Path, description = "Hex encoded sighash of the ballot"),
+    ),
+    responses(
+        (status = 200, description = "Status of the ballot", body = BallotStatus),
+        (status = 404, description = "Unknown election or ballot", body = ErrorResponse),
+    )
+)]
+#[rocket::get("/election/<id>/ballot/<sighash>")]
+pub fn get_ballot_status(
+    id: String,
+    sighash: String,
+    state: &State<Context>,
+) -> Result<Json<BallotStatus>, ApiError> {
+    (|| {
+        let connection = state.pool.get()?;
+        let (id_election, _, _) = get_election(&connection, &id)?;
+        let status = crate::db::get_ballot_status(&connection, id_election, &sighash)?;
+        Ok::<_, Error>(Json(status))
+    })()
+    .map_err(api_error)
+}
+
+/// Server-sent events with the ballots of the election as they are
+/// committed. A `resync` event means that some ballots were missed
+#[utoipa::path(
+    get,
+    path = "/election/{id}/events",
+    params(("id" = String, Path, description = "Election id")),
+    responses(
+        (status = 200, description = "Stream of `ballot` events, and `resync` events",
+            body = BallotEvent, content_type = "text/event-stream"),
+    )
+)]
+#[rocket::get("/election/<id>/events")]
+pub fn ballot_events(
+    id: String,
+    state: &State<Context>,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let mut rx = state.events.subscribe();
+    EventStream! {
+        loop {
+            let event: BallotEvent = select! {
+                e = rx.recv() => match e {
+                    Ok(e) => e,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => {
+                        yield Event::empty().event("resync");
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+            if event.election == id {
+                yield Event::json(&event).event("ballot");
+            }
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/election/{id}/num_ballots",
+    params(("id" = String, Path, description = "Election id")),
+    responses(
+        (status = 200, description = "Number of ballots", body = u32, content_type = "text/plain"),
+        (status = 404, description = "Unknown election", body = ErrorResponse),
+    )
+)]
 #[rocket::get("/election/<id>/num_ballots")]
-pub fn get_num_ballots(id: String, state: &State<Context>) -> Result<String, Custom<String>> {
+pub fn get_num_ballots(id: String, state: &State<Context>) -> Result<String, ApiError> {
     (|| {
         let connection = state.pool.get()?;
         let (id_election, _, _) = get_election(&connection, &id)?;
         let n = crate::db::get_num_ballots(&connection, id_election)?;
         Ok::<_, Error>(n.to_string())
     })()
-    .map_err(|e| Custom(Status::InternalServerError, e.to_string()))
+    .map_err(api_error)
 }
 
+#[utoipa::path(
+    post,
+    path = "/election/{id}/ballot",
+    params(("id" = String, Path, description = "Election id")),
+    request_body(content = Object, description = "Ballot", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Hash of the vote chain transaction",
+            body = String, content_type = "text/plain"),
+        (status = 403, description = "Election is closed", body = ErrorResponse),
+        (status = 409, description = "Note already spent", body = ErrorResponse),
+        (status = 422, description = "Invalid root, proof or signature", body = ErrorResponse),
+        (status = 429, description = "Too many ballots from the client, \
+            or verification queue full", body = ErrorResponse),
+    )
+)]
 #[rocket::post("/election/<id>/ballot", format = "json", data = "<ballot>")]
 pub async fn post_ballot(
     id: String,
     ballot: Json<Ballot>,
+    client: ClientAddress,
+    state: &State<Context>,
+) -> Result<String, ApiError> {
+    let res = async {
+        let (hash, _) = relay_ballots(id, vec![ballot.into_inner()], client, state).await?;
+        Ok::<_, Error>(hash)
+    };
+    res.await.map_err(api_error)
+}
+
+/// Submit a ballot and wait until it is committed, or rejected by the
+/// vote chain with the error of the rejection. After the commit
+/// deadline, the ballot is still pending
+#[utoipa::path(
+    post,
+    path = "/election/{id}/ballot/commit",
+    params(("id" = String, Path, description = "Election id")),
+    request_body(content = Object, description = "Ballot", content_type = "application/json"),
+    responses(
+        (status = 200, description = "Ballot included with its height, or still pending",
+            body = BallotStatus),
+        (status = 403, description = "Election is closed", body = ErrorResponse),
+        (status = 409, description = "Note already spent, \
+            also when the vote chain rejects the ballot", body = ErrorResponse),
+        (status = 422, description = "Invalid root, proof or signature", body = ErrorResponse),
+        (status = 429, description = "Too many ballots from the client, \
+            or verification queue full", body = ErrorResponse),
+    )
+)]
+#[rocket::post("/election/<id>/ballot/commit", format = "json", data = "<ballot>")]
+pub async fn post_ballot_commit(
+    id: String,
+    ballot: Json<Ballot>,
+    client: ClientAddress,
+    state: &State<Context>,
+) -> Result<Json<BallotStatus>, ApiError> {
+    let res = async {
+        let deadline = Instant::now() + state.commit_deadline;
+        // subscribe before the ballot can be committed
+        let mut events = state.events.subscribe();
+        let (_, sighashes) =
+            relay_ballots(id.clone(), vec![ballot.into_inner()], client, state).await?;
+        let status =
+            wait_for_commit(&state.pool, &mut events, &id, &sighashes[0], deadline).await?;
+        Ok::<_, Error>(Json(status))
+    };
+    res.await.map_err(api_error)
+}
+
+/// Submit the ballots of an allocation and wait until they are committed,
+/// like `/election/<id>/ballot/commit`. The ballots are included together
+/// or rejected together. Every ballot after the first spends the change
+/// of the ballot before it, and is anchored on the commitment root after
+/// it, appended to the last ballot of the election
+#[utoipa::path(
+    post,
+    path = "/election/{id}/ballots/commit",
+    params(("id" = String, Path, description = "Election id")),
+    request_body(content = Vec<Object>, description = "Ballots, at most 16",
+        content_type = "application/json"),
+    responses(
+        (status = 200, description = "Ballots included with their heights, or still pending",
+            body = Vec<BallotStatus>),
+        (status = 403, description = "Election is closed", body = ErrorResponse),
+        (status = 409, description = "Note already spent, \
+            also when the vote chain rejects the ballots", body = ErrorResponse),
+        (status = 422, description = "Invalid root, proof or signature. The root of a \
+            chained ballot is invalid when other ballots were committed before it",
+            body = ErrorResponse),
+        (status = 429, description = "Too many ballots from the client, \
+            or verification queue full", body = ErrorResponse),
+    )
+)]
+#[rocket::post("/election/<id>/ballots/commit", format = "json", data = "<ballots>")]
+pub async fn post_ballots_commit(
+    id: String,
+    ballots: Json<Vec<Ballot>>,
+    client: ClientAddress,
     state: &State<Context>,
-) -> Result<String, Custom<String>> {
+) -> Result<Json<Vec<BallotStatus>>, ApiError> {
     let res = async {
-        let comet_bft = state.comet_bft;
-        tracing::info!("Ballot received");
-        let tx = Tx {
-            id,
-            ballot: ballot.into_inner(),
+        let deadline = Instant::now() + state.commit_deadline;
+        // subscribe before the ballots can be committed
+        let mut events = state.events.subscribe();
+        let (_, sighashes) = relay_ballots(id.clone(), ballots.into_inner(), client, state).await?;
+        let mut statuses = vec![];
+        // the ballots are in the same block
+        for sighash in sighashes.iter() {
+            let status = wait_for_commit(&state.pool, &mut events, &id, sighash, deadline).await?;
+            statuses.push(status);
+        }
+        Ok::<_, Error>(Json(statuses))
+    };
+    res.await.map_err(api_error)
+}
+
+/// How often a ballot waiting for its commit checks for a rejection
+const COMMIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wait until the ballot is included, or until the deadline when it
+/// is still pending. A ballot rejected by the vote chain fails with
+/// the error of the rejection
+pub(crate) async fn wait_for_commit(
+    pool: &Pool<SqliteConnectionManager>,
+    events: &mut Receiver<BallotEvent>,
+    id: &str,
+    sighash: &[u8],
+    deadline: Instant,
+) -> Result<BallotStatus, VoteError> {
+    let hex_sighash = hex::encode(sighash);
+    loop {
+        let status = {
+            let connection = pool.get().map_err(anyhow::Error::from)?;
+            let (id_election, _, _) = get_election(&connection, id)?;
+            if let Some(e) = get_rejection(&connection, id_election, sighash)? {
+                return Err(VoteError::Remote(e));
+            }
+            crate::db::get_ballot_status(&connection, id_election, &hex_sighash)?
         };
-        let tx_bytes = bincode::serialize(&tx).unwrap();
-
-        let rpc_port = comet_bft - 1;
-        let tx_data = BASE64_STANDARD.encode(&tx_bytes);
-        let req_body = serde_json::json!({
-            "id": "",
-            "method": "broadcast_tx_sync",
-            "params": [tx_data]
-        });
-        let url = format!("http://127.0.0.1:{rpc_port}/v1");
-        tracing::info!("Post to {}", url);
-        let client = reqwest::Client::new();
-        let rep = client.post(&url)
-            .json(&req_body).send().await?.error_for_status()?;
-        let json_rep: Value = rep.json().await?;
-        tracing::info!("post ballot rep: {:?}", json_rep);
-        if let Some(error_msg) = json_rep.pointer("/error/data") {
-            anyhow::bail!(error_msg.as_str().unwrap().to_string());
+        if status.status != SubmissionStatus::Pending {
+            return Ok(status);
         }
-        let result = &json_rep.pointer("/result/hash")
-            .map(|v| v.as_str().unwrap().to_string()).unwrap_or_default();
+        // rejected ballots have no event and are polled
+        select! {
+            _ = events.recv() => {}
+            _ = sleep(COMMIT_POLL_INTERVAL) => {}
+            _ = sleep_until(deadline) => return Ok(status),
+        }
+    }
+}
 
-        Ok::<_, Error>(result.clone())
+/// Verify the ballots and broadcast them to CometBFT, in one
+/// transaction. Returns the hash of the transaction and the
+/// sighashes of the ballots
+async fn relay_ballots(
+    id: String,
+    ballots: Vec<Ballot>,
+    client: ClientAddress,
+    state: &State<Context>,
+) -> Result<(String, Vec<Vec<u8>>), Error> {
+    let comet_bft = state.comet_bft;
+    tracing::info!("{} ballots received", ballots.len());
+    let sighashes = ballots
+        .iter()
+        .map(|b| Ok(b.data.sighash()?.to_vec()))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let permit = state.admission.admit(client.0, ballots.len())?;
+    let connection = state.pool.get()?;
+    // check_tx does not verify the proofs of these ballots again
+    let verified = state.verified.clone();
+    // invalid ballots are rejected before they reach the mempool
+    let (id, ballots, checked) = spawn_blocking(move || {
+        let _permit = permit;
+        let checked = check_batch(&connection, &id, &ballots, &verified);
+        (id, ballots, checked)
+    })
+    .await?;
+    let record = |status: SubmissionStatus, error: Option<&ErrorResponse>| {
+        let connection = state.pool.get()?;
+        for sighash in sighashes.iter() {
+            store_submission(&connection, &id, sighash, status, error)?;
+        }
+        Ok::<_, Error>(())
     };
-    res.await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))
+    if let Err(e) = checked {
+        let _ = record(SubmissionStatus::Rejected, Some(&ErrorResponse::from(&e)));
+        return Err(e.into());
+    }
+    let tx_bytes = BatchTx { id: id.clone(), ballots }.encode();
+
+    let res = match &state.sequencer {
+        Some(sequencer) => {
+            let sequencer = sequencer.clone();
+            spawn_blocking(move || sequencer.broadcast_tx(tx_bytes)).await?
+        }
+        None => {
+            let rpc_port = comet_bft - 1;
+            let url = format!("http://127.0.0.1:{rpc_port}/v1");
+            tracing::info!("Post to {}", url);
+            CometRpc::new(&url).broadcast_tx_sync(&tx_bytes).await
+        }
+    };
+    match res {
+        Ok(hash) => {
+            record(SubmissionStatus::Pending, None)?;
+            Ok((hash, sighashes))
+        }
+        // check_tx rejection, with the error code set by the vote chain
+        Err(VoteError::Remote(e)) => {
+            // unknown elections have nowhere to record the rejection
+            let _ = record(SubmissionStatus::Rejected, Some(&e));
+            Err(VoteError::Remote(e).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::tokio::sync::broadcast;
+    use zcash_vote::errors::ErrorCode;
+
+    use super::*;
+    use crate::testing::{ballot, open_election, test_pool, test_runner};
+
+    #[rocket::async_test]
+    async fn rejected_ballots_fail_without_waiting_for_the_deadline() {
+        let pool = test_pool();
+        let (id, _) = open_election(&pool.get().unwrap(), "commit");
+        let (events, mut rx) = broadcast::channel(16);
+        let mut runner = test_runner(&pool, events);
+        let rejected = ballot(&id, 2, &[1]);
+        store_submission(
+            &pool.get().unwrap(),
+            &id,
+            &rejected.sighash,
+            SubmissionStatus::Pending,
+            None,
+        )
+        .unwrap();
+
+        let id2 = id.clone();
+        let block = spawn_blocking(move || {
+            runner.begin_block().unwrap();
+            runner.finalize_batch(&[ballot(&id2, 1, &[1])]).unwrap();
+            runner.finalize_batch(&[ballot(&id2, 2, &[1])]).unwrap_err();
+            runner.commit().unwrap();
+        });
+
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(30);
+        let res = wait_for_commit(&pool, &mut rx, &id, &rejected.sighash, deadline).await;
+        block.await.unwrap();
+        match res {
+            Err(VoteError::Remote(e)) => assert_eq!(e.code, ErrorCode::DoubleSpend),
+            _ => panic!("the rejection must be returned"),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
 }
@@ -0,0 +1,148 @@
+use std::{sync::Mutex, time::Duration};
+
+use sha2::{Digest as _, Sha256};
+use tendermint_abci::Application;
+use tendermint_proto::abci::{
+    CheckTxType, RequestCheckTx, RequestFinalizeBlock, RequestInfo, RequestPrepareProposal,
+};
+use zcash_vote::errors::{ErrorCode, ErrorResponse, VoteError};
+
+use crate::chain::VoteChain;
+
+/// In-process replacement of CometBFT, for a single node
+///
+/// Ballots that pass check_tx wait in the mempool until the next block.
+/// At every tick, the pending ballots are proposed, finalized and
+/// committed in a block, like a single validator would. Blocks are
+/// only made when there are ballots
+pub struct Sequencer {
+    chain: VoteChain,
+    mempool: Mutex<Vec<Vec<u8>>>,
+    block_time: Duration,
+}
+
+impl Sequencer {
+    pub fn new(chain: VoteChain, block_time: Duration) -> Self {
+        Self {
+            chain,
+            mempool: Mutex::new(vec![]),
+            block_time,
+        }
+    }
+
+    /// Add a transaction to the mempool once it passes check_tx, returns
+    /// its hash. A rejection keeps the error code of the vote chain,
+    /// like `CometRpc::broadcast_tx_sync`
+    pub fn broadcast_tx(&self, tx: Vec<u8>) -> Result<String, VoteError> {
+        let rep = self.chain.check_tx(RequestCheckTx {
+            tx: tx.clone().into(),
+            r#type: CheckTxType::New as i32,
+        });
+        if rep.code != 0 {
+            return Err(VoteError::Remote(ErrorResponse {
+                code: ErrorCode::from_abci_code(rep.code),
+                message: rep.log,
+            }));
+        }
+        Ok(self.add(tx))
+    }
+
+    /// Add a checked transaction to the mempool, returns its hash
+    fn add(&self, tx: Vec<u8>) -> String {
+        let hash = tx_hash(&tx);
+        self.mempool.lock().unwrap().push(tx);
+        hash
+    }
+
+    /// Make a block of the mempool every `block_time`, never returns
+    pub fn run(&self) {
+        loop {
+            std::thread::sleep(self.block_time);
+            let txs = std::mem::take(&mut *self.mempool.lock().unwrap());
+            if !txs.is_empty() {
+                self.make_block(txs);
+            }
+        }
+    }
+
+    fn make_block(&self, txs: Vec<Vec<u8>>) {
+        let info = self.chain.info(RequestInfo::default());
+        let height = info.last_block_height + 1;
+        // double spends within the block are left out of the proposal
+        let proposal = self.chain.prepare_proposal(RequestPrepareProposal {
+            txs: txs.into_iter().map(Into::into).collect(),
+            height,
+            ..Default::default()
+        });
+        let block = self.chain.finalize_block(RequestFinalizeBlock {
+            txs: proposal.txs,
+            height,
+            ..Default::default()
+        });
+        self.chain.commit();
+        tracing::info!("block {height}: {} ballots", block.tx_results.len());
+    }
+}
+
+/// Hash of a transaction, as reported by CometBFT
+fn tx_hash(tx: &[u8]) -> String {
+    hex::encode_upper(Sha256::digest(tx))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rocket::tokio::{sync::broadcast, time::Instant};
+    use zcash_vote::client::SubmissionStatus;
+
+    use super::*;
+    use crate::{
+        db::store_submission,
+        routes::wait_for_commit,
+        testing::{ballot, open_election, test_pool, test_tx},
+    };
+
+    #[test]
+    fn transactions_have_the_hash_of_cometbft() {
+        assert_eq!(
+            tx_hash(b"abc"),
+            "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn ballots_are_committed_in_the_next_block() {
+        let pool = test_pool();
+        let (id, _) = open_election(&pool.get().unwrap(), "standalone");
+        let (events, mut rx) = broadcast::channel(16);
+        let mut published = events.subscribe();
+        let (chain, runner) = VoteChain::new(pool.get().unwrap(), events, Default::default());
+        std::thread::spawn(move || runner.run());
+        let sequencer = Arc::new(Sequencer::new(chain, Duration::from_millis(50)));
+        let s = sequencer.clone();
+        std::thread::spawn(move || s.run());
+
+        // check_tx fails like with CometBFT, and nothing is added
+        match sequencer.broadcast_tx(vec![0xFF; 32]) {
+            Err(VoteError::Remote(e)) => assert_eq!(e.code, ErrorCode::InvalidData),
+            _ => panic!("an invalid transaction must be rejected"),
+        }
+        assert!(sequencer.mempool.lock().unwrap().is_empty());
+
+        let tx = ballot(&id, 1, &[1]);
+        let sighash = tx.sighash.clone();
+        store_submission(&pool.get().unwrap(), &id, &sighash, SubmissionStatus::Pending, None)
+            .unwrap();
+        let tx = test_tx(&[tx]);
+        assert_eq!(sequencer.add(tx.clone()), tx_hash(&tx));
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let status = wait_for_commit(&pool, &mut rx, &id, &sighash, deadline).await.unwrap();
+        assert_eq!(status.status, SubmissionStatus::Included);
+        assert_eq!(status.height, Some(1));
+        let event = published.recv().await.unwrap();
+        assert_eq!((event.sighash, event.height), (hex::encode(&sighash), 1));
+        assert!(sequencer.mempool.lock().unwrap().is_empty());
+    }
+}
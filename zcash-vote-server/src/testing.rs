@@ -0,0 +1,101 @@
+//! Fixtures of the server tests: databases with open elections,
+//! and ballots whose proofs are not needed by the vote chain
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rocket::tokio::sync::broadcast;
+use rusqlite::Connection;
+use orchard::vote::OrchardHash;
+use zcash_vote::{
+    as_byte256, client::BallotEvent, db::store_cmx, election::Election, trees::compute_cmx_root,
+};
+
+use crate::{
+    chain::{BallotTx, VoteChain, VoteChainRunner},
+    db::{create_schema, get_last_frontier, store_election, store_election_frontier},
+};
+
+/// Pool on a new database file with the server schema
+pub fn test_pool() -> Pool<SqliteConnectionManager> {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "zcash-vote-server-test-{}-{}.db",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let _ = std::fs::remove_file(&path);
+    let manager = SqliteConnectionManager::file(&path)
+        .with_init(|c| c.execute_batch("PRAGMA foreign_keys = ON"));
+    let pool = Pool::new(manager).unwrap();
+    create_schema(&pool.get().unwrap()).unwrap();
+    pool
+}
+
+/// Vote chain runner on the pool, that publishes to `events`
+pub fn test_runner(
+    pool: &Pool<SqliteConnectionManager>,
+    events: broadcast::Sender<BallotEvent>,
+) -> VoteChainRunner {
+    let (_, runner) = VoteChain::new(pool.get().unwrap(), events, Default::default());
+    runner
+}
+
+/// Commitment that is a valid field element
+pub fn leaf(n: u8) -> Vec<u8> {
+    let mut h = vec![0u8; 32];
+    h[0] = n;
+    h
+}
+
+/// Register an open election whose snapshot has two notes.
+/// Returns its id and `id_election`
+pub fn open_election(connection: &Connection, name: &str) -> (String, u32) {
+    let election = Election {
+        name: name.to_string(),
+        ..Election::default()
+    };
+    let id_election = store_election(connection, &election, false).unwrap();
+    for n in 1..=2 {
+        store_cmx(connection, id_election, &leaf(n)).unwrap();
+    }
+    let (_, frontier) = compute_cmx_root(connection, id_election).unwrap();
+    store_election_frontier(connection, id_election, &frontier.unwrap()).unwrap();
+    (election.id(), id_election)
+}
+
+/// Ballot `n` of the election `id`, with one action for every
+/// domain nullifier `[d; 32]` of `dnfs`. Its anchor is only
+/// checked when it is chained
+pub fn ballot(id: &str, n: u8, dnfs: &[u8]) -> BallotTx {
+    BallotTx {
+        id: id.to_string(),
+        sighash: vec![n; 32],
+        anchor: vec![],
+        dnfs: dnfs.iter().map(|d| vec![*d; 32]).collect(),
+        cmxs: dnfs.iter().map(|_| leaf(n)).collect(),
+        data: "{}".to_string(),
+    }
+}
+
+/// Prefix of the transactions of test ballots. They have no proofs
+/// and skip check_tx, but go in blocks like the other ballots
+pub const TEST_TX: &[u8] = b"TEST";
+
+/// Transaction of test ballots, for the block of a sequencer
+pub fn test_tx(ballots: &[BallotTx]) -> Vec<u8> {
+    let mut tx = TEST_TX.to_vec();
+    tx.extend(bincode::serialize(ballots).unwrap());
+    tx
+}
+
+/// Root after the commitments of `ballots`, appended
+/// to the last ballot of the election
+pub fn root_after(connection: &Connection, id_election: u32, ballots: &[&BallotTx]) -> Vec<u8> {
+    let (_, mut frontier) = get_last_frontier(connection, id_election).unwrap();
+    for cmx in ballots.iter().flat_map(|b| b.cmxs.iter()) {
+        frontier.append(OrchardHash(as_byte256(cmx)));
+    }
+    frontier.root().to_vec()
+}
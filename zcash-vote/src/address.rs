@@ -4,16 +4,15 @@ use anyhow::Result;
 use bech32::{FromBase32, ToBase32};
 use orchard::Address;
 
-pub struct VoteAddress(pub Address);
+use crate::network::Network;
 
-const VOTE_HRP: &str = "zvote";
+pub struct VoteAddress(pub Address, pub Network);
 
 impl VoteAddress {
     pub fn decode(s: &str) -> Result<Self> {
         let (hrp, data, _) = bech32::decode(s)?;
-        if hrp != VOTE_HRP {
-            anyhow::bail!("Invalid Address (incorrect prefix)");
-        }
+        let network = Network::from_vote_hrp(&hrp)
+            .ok_or(anyhow::anyhow!("Invalid Address (incorrect prefix)"))?;
         let data = Vec::<u8>::from_base32(&data)?;
         if data.len() != 43 {
             anyhow::bail!("Invalid Address (incorrect length)")
@@ -23,7 +22,16 @@ impl VoteAddress {
             anyhow::bail!("Invalid Address (invalid data)")
         }
         let address = address.unwrap();
-        Ok(VoteAddress(address))
+        Ok(VoteAddress(address, network))
+    }
+
+    /// Decode an address and check that it belongs to the given network
+    pub fn decode_for(network: Network, s: &str) -> Result<Self> {
+        let address = Self::decode(s)?;
+        if address.1 != network {
+            anyhow::bail!("Invalid Address (wrong network)");
+        }
+        Ok(address)
     }
 
     pub fn encode(&self) -> String {
@@ -31,7 +39,7 @@ impl VoteAddress {
         let address = address.to_raw_address_bytes();
         let address = address.to_base32();
 
-        bech32::encode(VOTE_HRP, &address, bech32::Variant::Bech32m).unwrap()
+        bech32::encode(self.1.vote_hrp(), &address, bech32::Variant::Bech32m).unwrap()
     }
 }
 
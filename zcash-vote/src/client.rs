@@ -0,0 +1,566 @@
+use std::{future::Future, time::Duration};
+
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use orchard::vote::Ballot;
+use rand::Rng as _;
+use reqwest::{Client, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    election::Election,
+    errors::{ErrorCode, ErrorResponse, VoteError},
+    Result,
+};
+
+/// Most ballots returned by one request of the ballot range route
+pub const MAX_BALLOTS_PER_PAGE: u32 = 500;
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionStatus {
+    /// Accepted by check_tx, waiting for a block
+    Pending,
+    /// Finalized at `height`
+    Included,
+    /// Refused by the vote chain, see `reason`
+    Rejected,
+}
+
+/// Status of a submitted ballot, identified by its sighash
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BallotStatus {
+    pub sighash: String,
+    pub status: SubmissionStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Root of the commitment tree after the ballot, when included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmx_root: Option<String>,
+    /// Nullifiers spent by the ballot, when included
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dnfs: Vec<String>,
+}
+
+/// Pushed by the server when a ballot is committed
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BallotEvent {
+    pub election: String,
+    pub height: u32,
+    pub sighash: String,
+    /// Root of the commitment tree after the ballot
+    pub cmx_root: String,
+    /// Nullifiers spent by the ballot
+    #[serde(default)]
+    pub dnfs: Vec<String>,
+}
+
+/// Entry of the list of elections registered on the server
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ElectionSummary {
+    pub id: String,
+    pub name: String,
+    pub start_height: u32,
+    pub end_height: u32,
+    pub closed: bool,
+    pub num_ballots: u32,
+    /// Id of the multi-question election this question belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+}
+
+/// Current state of an election on the vote chain
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ElectionStatus {
+    pub id: String,
+    pub closed: bool,
+    pub num_ballots: u32,
+    /// Root of the commitment tree, including the ballots
+    pub cmx_root: String,
+    /// Height and app hash of the vote chain
+    pub height: u32,
+    pub app_hash: String,
+}
+
+/// Line of the ballot range of an election
+#[derive(Deserialize)]
+struct HeightBallot<B> {
+    height: u32,
+    ballot: B,
+}
+
+/// Ballots of a page of the ballot range, at consecutive heights
+/// from `start` and up to `end`
+fn parse_page<B: serde::de::DeserializeOwned>(
+    body: &str,
+    start: u32,
+    end: u32,
+) -> Result<Vec<(u32, B)>> {
+    let mut ballots = vec![];
+    for l in body.lines().filter(|l| !l.trim().is_empty()) {
+        let HeightBallot { height, ballot } = serde_json::from_str::<HeightBallot<B>>(l)
+            .map_err(|e| VoteError::InvalidJson(e.to_string()))?;
+        let expected = start + ballots.len() as u32;
+        if height != expected || height > end {
+            return Err(VoteError::InvalidJson(format!(
+                "ballot at height {height} instead of {expected}, up to {end}"
+            )));
+        }
+        ballots.push((height, ballot));
+    }
+    Ok(ballots)
+}
+
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// Timeout of a request, the event stream has none
+    pub timeout: Duration,
+    /// Rounds of attempts on every server after the first one
+    pub retries: u32,
+    /// Wait before the first retry, doubled at every round
+    pub backoff: Duration,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Client of the vote server API, for a set of servers that
+/// run the same vote chain
+///
+/// Requests go to the servers in turn, starting from a random one,
+/// until one of them answers. Only network failures are retried;
+/// an error reported by a server is returned as `VoteError::Remote`
+#[derive(Clone, Debug)]
+pub struct VoteClient {
+    client: Client,
+    servers: Vec<String>,
+    config: ClientConfig,
+}
+
+impl VoteClient {
+    /// `servers` are the base URLs of the servers, without the
+    /// `/election/<id>` path
+    pub fn new(servers: Vec<String>, config: ClientConfig) -> Result<Self> {
+        if servers.is_empty() {
+            return Err(VoteError::Network("no server".to_string()));
+        }
+        let client = Client::builder().timeout(config.timeout).build()?;
+        let servers = servers
+            .into_iter()
+            .map(|s| s.trim().trim_end_matches('/').to_string())
+            .collect();
+        Ok(VoteClient {
+            client,
+            servers,
+            config,
+        })
+    }
+
+    /// Client for the election URLs of a voter, which must all be
+    /// for the same election. Returns the election id too
+    pub fn for_election(urls: &[String], config: ClientConfig) -> Result<(Self, String)> {
+        let mut servers = vec![];
+        let mut election_id: Option<String> = None;
+        for url in urls.iter() {
+            let (server, id) = split_election_url(url)?;
+            match &election_id {
+                Some(e) if *e != id => {
+                    return Err(VoteError::InvalidElection(format!(
+                        "{url} is not the URL of election {e}"
+                    )));
+                }
+                _ => election_id = Some(id),
+            }
+            servers.push(server);
+        }
+        let client = VoteClient::new(servers, config)?;
+        Ok((client, election_id.unwrap_or_default()))
+    }
+
+    /// Number of registered elections
+    pub async fn num_elections(&self) -> Result<u32> {
+        let n = self.get_text("").await?;
+        parse_count(&n)
+    }
+
+    pub async fn elections(&self) -> Result<Vec<ElectionSummary>> {
+        self.get_json("/elections").await
+    }
+
+    pub async fn election(&self, id: &str) -> Result<Election> {
+        self.get_json(&format!("/election/{id}")).await
+    }
+
+    pub async fn election_status(&self, id: &str) -> Result<ElectionStatus> {
+        self.get_json(&format!("/election/{id}/status")).await
+    }
+
+    pub async fn num_ballots(&self, id: &str) -> Result<u32> {
+        let n = self.get_text(&format!("/election/{id}/num_ballots")).await?;
+        parse_count(&n)
+    }
+
+    pub async fn ballot_at(&self, id: &str, height: u32) -> Result<Ballot> {
+        self.get_json(&format!("/election/{id}/ballot/height/{height}"))
+            .await
+    }
+
+    /// Ballots from height `start` up to `end` inclusive, with their heights
+    ///
+    /// The server returns at most `MAX_BALLOTS_PER_PAGE` ballots as newline
+    /// delimited JSON, each with its height. The height of the last one is
+    /// the cursor for the next page. The heights follow each other from
+    /// `start`, a page that skips a ballot is an error
+    pub async fn ballot_range(&self, id: &str, start: u32, end: u32) -> Result<Vec<(u32, Ballot)>> {
+        let body = self
+            .get_text(&format!("/election/{id}/ballots?start={start}&end={end}"))
+            .await?;
+        parse_page(&body, start, end)
+    }
+
+    /// Status of the ballot with the given sighash, None if no server
+    /// has seen it
+    ///
+    /// Every server is asked because until the ballot is included, only
+    /// the server that received it knows about it
+    pub async fn ballot_status(&self, id: &str, sighash: &str) -> Result<Option<BallotStatus>> {
+        let path = format!("/election/{id}/ballot/{sighash}");
+        let mut error = None;
+        for server in self.servers.iter() {
+            match self.fetch_text(server, &path).await {
+                Ok(body) => {
+                    let status = serde_json::from_str(&body)
+                        .map_err(|e| VoteError::InvalidJson(e.to_string()))?;
+                    return Ok(Some(status));
+                }
+                Err(VoteError::Remote(e)) if e.code == ErrorCode::NotFound => {}
+                Err(e) => error = Some(e),
+            }
+        }
+        // an unreachable server may know the ballot
+        match error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    /// Submit a ballot, returns the hash of the vote chain transaction
+    pub async fn post_ballot(&self, id: &str, ballot: &Ballot) -> Result<String> {
+        let path = format!("/election/{id}/ballot");
+        self.call(|server| {
+            let request = self.client.post(format!("{server}{path}")).json(ballot);
+            async move {
+                let rep = check_status(request.send().await?).await?;
+                Ok(rep.text().await?)
+            }
+        })
+        .await
+    }
+
+    /// Submit a ballot and wait for the vote chain to include or reject it.
+    /// A rejected ballot fails with the error of the vote chain
+    ///
+    /// The ballot is still pending when it was not committed before the
+    /// deadline of the server, which must be shorter than the request timeout
+    pub async fn post_ballot_commit(&self, id: &str, ballot: &Ballot) -> Result<BallotStatus> {
+        let path = format!("/election/{id}/ballot/commit");
+        self.call(|server| {
+            let request = self.client.post(format!("{server}{path}")).json(ballot);
+            async move {
+                let rep = check_status(request.send().await?).await?;
+                let body = rep.text().await?;
+                serde_json::from_str(&body).map_err(|e| VoteError::InvalidJson(e.to_string()))
+            }
+        })
+        .await
+    }
+
+    /// Submit the ballots of an allocation and wait for the vote chain
+    /// to include them, together, or to reject them
+    ///
+    /// Every ballot after the first spends the change of the ballot
+    /// before it and is anchored on the commitment root after it. The
+    /// server rejects them with `BadRoot` when other ballots were
+    /// committed after the last ballot known to the client
+    pub async fn post_ballots_commit(
+        &self,
+        id: &str,
+        ballots: &[Ballot],
+    ) -> Result<Vec<BallotStatus>> {
+        let path = format!("/election/{id}/ballots/commit");
+        self.call(|server| {
+            let request = self.client.post(format!("{server}{path}")).json(ballots);
+            async move {
+                let rep = check_status(request.send().await?).await?;
+                let body = rep.text().await?;
+                serde_json::from_str(&body).map_err(|e| VoteError::InvalidJson(e.to_string()))
+            }
+        })
+        .await
+    }
+
+    /// Follow the ballot event stream of the election until the
+    /// server closes it
+    ///
+    /// `f` is called with every committed ballot, and with None when
+    /// the server dropped events and the client must sync again
+    pub async fn watch_ballots(
+        &self,
+        id: &str,
+        mut f: impl FnMut(Option<BallotEvent>),
+    ) -> Result<()> {
+        let path = format!("/election/{id}/events");
+        // the stream stays open, it cannot have a timeout
+        let client = Client::new();
+        let mut rep = self
+            .call(|server| {
+                let request = client.get(format!("{server}{path}"));
+                async move { check_status(request.send().await?).await }
+            })
+            .await?;
+        let mut buffer = String::new();
+        while let Some(chunk) = rep.chunk().await? {
+            buffer.push_str(&String::from_utf8_lossy(&chunk));
+            // events are separated by a blank line
+            while let Some(end) = buffer.find("\n\n") {
+                let message = buffer[..end].to_string();
+                buffer.drain(..end + 2);
+                let mut event = "message";
+                let mut data = String::new();
+                for line in message.lines() {
+                    if let Some(e) = line.strip_prefix("event:") {
+                        event = e.trim();
+                    } else if let Some(d) = line.strip_prefix("data:") {
+                        data.push_str(d.trim());
+                    }
+                }
+                match event {
+                    "ballot" => {
+                        let e = serde_json::from_str::<BallotEvent>(&data)
+                            .map_err(|e| VoteError::InvalidJson(e.to_string()))?;
+                        f(Some(e));
+                    }
+                    "resync" => f(None),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_text(&self, path: &str) -> Result<String> {
+        self.call(|server| self.fetch_text(server, path)).await
+    }
+
+    async fn fetch_text(&self, server: &str, path: &str) -> Result<String> {
+        let rep = self.client.get(format!("{server}{path}")).send().await?;
+        let rep = check_status(rep).await?;
+        Ok(rep.text().await?)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let body = self.get_text(path).await?;
+        serde_json::from_str(&body).map_err(|e| VoteError::InvalidJson(e.to_string()))
+    }
+
+    /// Run the request on every server until one succeeds, and again
+    /// after a backoff while the failures are network errors
+    async fn call<T, F, Fut>(&self, request: F) -> Result<T>
+    where
+        F: Fn(&str) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let first = rand::thread_rng().gen_range(0..self.servers.len());
+        let mut backoff = self.config.backoff;
+        let mut error = None;
+        for attempt in 0..=self.config.retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            for i in 0..self.servers.len() {
+                let server = &self.servers[(first + i) % self.servers.len()];
+                match request(server).await {
+                    Ok(r) => return Ok(r),
+                    Err(e) if e.code() == ErrorCode::Network => {
+                        log::info!("{server}: {e}");
+                        error = Some(e);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Err(error.unwrap())
+    }
+}
+
+/// Split an election URL into the server URL and the election id
+pub fn split_election_url(url: &str) -> Result<(String, String)> {
+    let url = url.trim().trim_end_matches('/');
+    match url.rsplit_once("/election/") {
+        Some((server, id)) if !id.is_empty() && !id.contains('/') => {
+            Ok((server.to_string(), id.to_string()))
+        }
+        _ => Err(VoteError::InvalidElection(format!(
+            "{url} is not an election URL"
+        ))),
+    }
+}
+
+fn parse_count(s: &str) -> Result<u32> {
+    s.trim()
+        .parse::<u32>()
+        .map_err(|_| VoteError::InvalidJson(format!("invalid count {s}")))
+}
+
+/// Server errors carry an `ErrorResponse` body
+async fn check_status(rep: Response) -> Result<Response> {
+    if rep.status().is_success() {
+        return Ok(rep);
+    }
+    let status = rep.status();
+    match rep.json::<ErrorResponse>().await {
+        Ok(e) => Err(VoteError::Remote(e)),
+        Err(_) => Err(VoteError::Network(format!("HTTP status {status}"))),
+    }
+}
+
+/// JSON-RPC client of the CometBFT node of a vote server
+#[derive(Clone, Debug)]
+pub struct CometRpc {
+    client: Client,
+    url: String,
+}
+
+impl CometRpc {
+    pub fn new(url: &str) -> Self {
+        CometRpc {
+            client: Client::new(),
+            url: url.to_string(),
+        }
+    }
+
+    /// Broadcast a transaction once it passes check_tx, returns
+    /// its hash. A rejection keeps the error code of the vote chain
+    pub async fn broadcast_tx_sync(&self, tx: &[u8]) -> Result<String> {
+        let req_body = serde_json::json!({
+            "id": "",
+            "method": "broadcast_tx_sync",
+            "params": [BASE64_STANDARD.encode(tx)]
+        });
+        let rep = self
+            .client
+            .post(&self.url)
+            .json(&req_body)
+            .send()
+            .await
+            .and_then(|rep| rep.error_for_status())
+            .map_err(|e| VoteError::Network(e.to_string()))?;
+        let json_rep: Value = rep.json().await?;
+        log::info!("broadcast_tx_sync rep: {:?}", json_rep);
+        if let Some(data) = json_rep.pointer("/error/data") {
+            return Err(VoteError::Remote(rpc_error(data)));
+        }
+        let code = json_rep
+            .pointer("/result/code")
+            .and_then(Value::as_u64)
+            .unwrap_or_default();
+        if code != 0 {
+            let message = json_rep
+                .pointer("/result/log")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            return Err(VoteError::Remote(ErrorResponse {
+                code: ErrorCode::from_abci_code(code as u32),
+                message,
+            }));
+        }
+        let hash = json_rep
+            .pointer("/result/hash")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        Ok(hash)
+    }
+}
+
+/// Rejection reported in the `data` of an RPC error, either as the
+/// `code` and `log` of the check, or as a message that has the code
+fn rpc_error(data: &Value) -> ErrorResponse {
+    let (code, message) = match data {
+        Value::String(message) => (code_in_message(message), message.clone()),
+        data => (
+            data.get("code").and_then(Value::as_u64),
+            data.get("log")
+                .and_then(Value::as_str)
+                .map(String::from)
+                .unwrap_or_else(|| data.to_string()),
+        ),
+    };
+    ErrorResponse {
+        code: ErrorCode::from_abci_code(code.unwrap_or_default() as u32),
+        message,
+    }
+}
+
+/// Number after the first "code" of the message, as in `code: 2`
+fn code_in_message(message: &str) -> Option<u64> {
+    let (_, rest) = message.split_once("code")?;
+    let rest = rest.trim_start_matches(|c: char| c == ':' || c == '=' || c.is_whitespace());
+    let digits = rest.split(|c: char| !c.is_ascii_digit()).next()?;
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn rpc_errors_keep_the_abci_code() {
+        let e = rpc_error(&json!({ "code": 2, "log": "Double spend" }));
+        assert_eq!((e.code, e.message.as_str()), (ErrorCode::DoubleSpend, "Double spend"));
+        let e = rpc_error(&json!("CheckTx failed with code: 3, cmx root"));
+        assert_eq!(e.code, ErrorCode::BadRoot);
+        let e = rpc_error(&json!("tx already exists in cache"));
+        assert_eq!(e.code, ErrorCode::Internal);
+    }
+
+    fn page(heights: &[u32]) -> String {
+        heights
+            .iter()
+            .map(|h| format!("{}\n", json!({ "height": h, "ballot": { "n": h } })))
+            .collect()
+    }
+
+    #[test]
+    fn ballot_pages_have_consecutive_heights() {
+        let ballots = parse_page::<Value>(&page(&[3, 4, 5]), 3, 10).unwrap();
+        assert_eq!(ballots.iter().map(|(h, _)| *h).collect::<Vec<_>>(), [3, 4, 5]);
+        assert_eq!(ballots[1].1, json!({ "n": 4 }));
+        assert!(parse_page::<Value>("", 3, 10).unwrap().is_empty());
+
+        // a gap, a reordering, a page that does not begin at start
+        assert!(parse_page::<Value>(&page(&[3, 5, 6]), 3, 10).is_err());
+        assert!(parse_page::<Value>(&page(&[3, 4, 3]), 3, 10).is_err());
+        assert!(parse_page::<Value>(&page(&[4, 5]), 3, 10).is_err());
+        // or that goes past the end
+        assert!(parse_page::<Value>(&page(&[9, 10, 11]), 9, 10).is_err());
+    }
+}
@@ -8,16 +8,20 @@ use pasta_curves::Fp;
 use rusqlite::{params, Connection, OptionalExtension as _};
 use serde::{Deserialize, Serialize};
 
-use crate::as_byte256;
+use crate::{
+    as_byte256,
+    migration::{migrate, Migration, SCHEMA_VERSION},
+};
+
+/// Migrations of the base schema, in order
+/// Never modify a published step; append a new one instead
+pub const MIGRATIONS: &[Migration] = &[migration_001, migration_002, migration_003];
 
 pub fn create_schema(connection: &Connection) -> Result<()> {
-    connection.execute(
-        "CREATE TABLE IF NOT EXISTS properties(
-        id_property INTEGER PRIMARY KEY,
-        name TEXT NOT NULL UNIQUE,
-        value TEXT NOT NULL)",
-        [],
-    )?;
+    migrate(connection, SCHEMA_VERSION, MIGRATIONS)
+}
+
+fn migration_001(connection: &Connection) -> Result<()> {
     connection.execute(
         "CREATE TABLE IF NOT EXISTS ballots(
         id_ballot INTEGER PRIMARY KEY,
@@ -86,6 +90,91 @@ pub fn create_schema(connection: &Connection) -> Result<()> {
     Ok(())
 }
 
+/// Databases created before the migrations have the column already
+fn migration_002(connection: &Connection) -> Result<()> {
+    let has_account = connection
+        .prepare("SELECT 1 FROM pragma_table_info('notes') WHERE name = 'account'")?
+        .exists([])?;
+    if !has_account {
+        connection.execute(
+            "ALTER TABLE notes ADD COLUMN account INTEGER NOT NULL DEFAULT 0",
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// Uniqueness is per election so that a database can hold
+/// several elections with overlapping block ranges
+fn migration_003(connection: &Connection) -> Result<()> {
+    connection.execute_batch(
+        "CREATE TABLE ballots_new(
+        id_ballot INTEGER PRIMARY KEY,
+        election INTEGER NOT NULL,
+        height INTEGER NOT NULL,
+        hash BLOB NOT NULL,
+        data BLOB NOT NULL,
+        CONSTRAINT u_ballots UNIQUE (election, hash));
+        INSERT INTO ballots_new(id_ballot, election, height, hash, data)
+        SELECT id_ballot, election, height, hash, data FROM ballots;
+        DROP TABLE ballots;
+        ALTER TABLE ballots_new RENAME TO ballots;
+
+        CREATE TABLE nfs_new(
+        id_nf INTEGER PRIMARY KEY NOT NULL,
+        election INTEGER NOT NULL,
+        hash BLOB NOT NULL,
+        CONSTRAINT u_nfs UNIQUE (election, hash));
+        INSERT INTO nfs_new(id_nf, election, hash)
+        SELECT id_nf, election, hash FROM nfs;
+        DROP TABLE nfs;
+        ALTER TABLE nfs_new RENAME TO nfs;
+
+        CREATE TABLE dnfs_new(
+        id_dnf INTEGER PRIMARY KEY NOT NULL,
+        election INTEGER NOT NULL,
+        hash BLOB NOT NULL,
+        CONSTRAINT u_dnfs UNIQUE (election, hash));
+        INSERT INTO dnfs_new(id_dnf, election, hash)
+        SELECT id_dnf, election, hash FROM dnfs;
+        DROP TABLE dnfs;
+        ALTER TABLE dnfs_new RENAME TO dnfs;
+
+        CREATE TABLE cmxs_new(
+        id_cmx INTEGER PRIMARY KEY NOT NULL,
+        election INTEGER NOT NULL,
+        hash BLOB NOT NULL,
+        CONSTRAINT u_cmxs UNIQUE (election, hash));
+        INSERT INTO cmxs_new(id_cmx, election, hash)
+        SELECT id_cmx, election, hash FROM cmxs;
+        DROP TABLE cmxs;
+        ALTER TABLE cmxs_new RENAME TO cmxs;
+
+        CREATE TABLE notes_new(
+        id_note INTEGER PRIMARY KEY,
+        election INTEGER NOT NULL,
+        account INTEGER NOT NULL DEFAULT 0,
+        position INTEGER NOT NULL,
+        height INTEGER NOT NULL,
+        txid BLOB NOT NULL,
+        value INTEGER NOT NULL,
+        div BLOB NOT NULL,
+        rseed BLOB NOT NULL,
+        nf BLOB NOT NULL,
+        dnf BLOB NOT NULL,
+        rho BLOB NOT NULL,
+        spent INTEGER,
+        CONSTRAINT u_notes UNIQUE (election, position));
+        INSERT INTO notes_new(id_note, election, account, position, height, txid,
+        value, div, rseed, nf, dnf, rho, spent)
+        SELECT id_note, election, account, position, height, txid,
+        value, div, rseed, nf, dnf, rho, spent FROM notes;
+        DROP TABLE notes;
+        ALTER TABLE notes_new RENAME TO notes;",
+    )?;
+    Ok(())
+}
+
 pub fn store_prop(connection: &Connection, name: &str, value: &str) -> Result<()> {
     connection.execute(
         "INSERT INTO properties(name, value) VALUES (?1, ?2)
@@ -106,6 +195,11 @@ pub fn load_prop(connection: &Connection, name: &str) -> Result<Option<String>>
     Ok(value)
 }
 
+pub fn delete_prop(connection: &Connection, name: &str) -> Result<()> {
+    connection.execute("DELETE FROM properties WHERE name = ?1", [name])?;
+    Ok(())
+}
+
 pub fn store_dnf(connection: &Connection, id_election: u32, dnf: &[u8]) -> Result<()> {
     connection.execute(
         "INSERT INTO dnfs(election, hash) VALUES (?1, ?2)",
@@ -117,6 +211,7 @@ pub fn store_dnf(connection: &Connection, id_election: u32, dnf: &[u8]) -> Resul
 pub fn store_note(
     connection: &Connection,
     id_election: u32,
+    account: u32,
     domain: Fp,
     fvk: &FullViewingKey,
     height: u32,
@@ -132,10 +227,11 @@ pub fn store_note(
     let rho = note.rho().to_bytes();
     connection.execute(
         "INSERT INTO notes
-        (election, position, height, txid, value, div, rseed, nf, dnf, rho, spent)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, NULL)",
+        (election, account, position, height, txid, value, div, rseed, nf, dnf, rho, spent)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, NULL)",
         params![
             id_election,
+            account,
             position,
             height,
             txid,
@@ -161,14 +257,15 @@ pub fn mark_spent(connection: &Connection, id: u32, height: u32) -> Result<()> {
 pub fn list_notes(
     connection: &Connection,
     id_election: u32,
+    account: u32,
     fvk: &FullViewingKey,
     scope: Scope,
 ) -> Result<Vec<(orchard::Note, u32)>> {
     let mut s = connection.prepare(
         "SELECT position, height, txid, value, div, rseed, nf, dnf, rho
-        FROM notes WHERE spent IS NULL AND election = ?1",
+        FROM notes WHERE spent IS NULL AND election = ?1 AND account = ?2",
     )?;
-    let notes = s.query_map([id_election], |r| {
+    let notes = s.query_map([id_election, account], |r| {
         let position = r.get::<_, u32>(0)?;
         let height = r.get::<_, u32>(1)?;
         let txid = r.get::<_, Vec<u8>>(2)?;
@@ -247,3 +344,73 @@ impl Note {
         (note, self.position)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migration::{get_schema_version, BASELINE_SCHEMA};
+
+    fn count(connection: &Connection, table: &str) -> u32 {
+        connection
+            .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |r| r.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn baseline_database_migrates_to_head() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(BASELINE_SCHEMA).unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO ballots(election, height, hash, data) VALUES (0, 1, x'01', '{}');
+                INSERT INTO nfs(election, hash) VALUES (0, x'02');
+                INSERT INTO dnfs(election, hash) VALUES (0, x'03');
+                INSERT INTO cmxs(election, hash) VALUES (0, x'04');
+                INSERT INTO cmx_roots(election, height, hash) VALUES (0, 0, x'05');
+                INSERT INTO cmx_frontiers(election, height, frontier) VALUES (0, 0, '{}');
+                INSERT INTO notes(election, position, height, txid, value,
+                div, rseed, nf, dnf, rho, spent)
+                VALUES (0, 7, 100, x'06', 1000, x'07', x'08', x'02', x'03', x'09', NULL);",
+            )
+            .unwrap();
+
+        create_schema(&connection).unwrap();
+
+        assert_eq!(
+            get_schema_version(&connection, SCHEMA_VERSION).unwrap() as usize,
+            MIGRATIONS.len()
+        );
+        for table in ["ballots", "nfs", "dnfs", "cmxs", "cmx_roots", "cmx_frontiers", "notes"] {
+            assert_eq!(count(&connection, table), 1, "{table}");
+        }
+        let (account, position, value) = connection
+            .query_row("SELECT account, position, value FROM notes", [], |r| {
+                Ok((r.get::<_, u32>(0)?, r.get::<_, u32>(1)?, r.get::<_, u64>(2)?))
+            })
+            .unwrap();
+        // notes from before accounts belong to the first one
+        assert_eq!((account, position, value), (0, 7, 1000));
+
+        // hashes are unique per election
+        store_dnf(&connection, 1, &[3]).unwrap();
+        store_cmx(&connection, 1, &[4]).unwrap();
+        assert!(store_dnf(&connection, 0, &[3]).is_err());
+    }
+
+    #[test]
+    fn migrations_run_once() {
+        let connection = Connection::open_in_memory().unwrap();
+        create_schema(&connection).unwrap();
+        create_schema(&connection).unwrap();
+        assert_eq!(
+            get_schema_version(&connection, SCHEMA_VERSION).unwrap() as usize,
+            MIGRATIONS.len()
+        );
+        let has_account = connection
+            .prepare("SELECT 1 FROM pragma_table_info('notes') WHERE name = 'account'")
+            .unwrap()
+            .exists([])
+            .unwrap();
+        assert!(has_account);
+    }
+}
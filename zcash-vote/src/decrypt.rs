@@ -9,24 +9,36 @@ use orchard::{
 use zcash_address::unified::{self, Container, Encoding, Fvk};
 use zcash_note_encryption::{try_compact_note_decryption, EphemeralKeyBytes};
 
-use crate::{as_byte256, rpc::CompactOrchardAction};
+use crate::{as_byte256, network::Network, rpc::CompactOrchardAction};
 
-pub fn to_sk(key: &str) -> Result<Option<SpendingKey>> {
+pub fn to_sk(
+    network: Network,
+    key: &str,
+    account: u32,
+    passphrase: &str,
+) -> Result<Option<SpendingKey>> {
     if let Ok(m) = Mnemonic::from_phrase(key) {
-        let seed = m.to_seed("");
-        let spk =
-            SpendingKey::from_zip32_seed(&seed, zcash_primitives::constants::mainnet::COIN_TYPE, 0)
-                .map_err(|_| anyhow!("Failed to derive zip-32"))?;
+        let seed = m.to_seed(passphrase);
+        let spk = SpendingKey::from_zip32_seed(&seed, network.coin_type(), account)
+            .map_err(|_| anyhow!("Failed to derive zip-32"))?;
         return Ok(Some(spk));
     }
     Ok(None)
 }
 
-pub fn to_fvk(key: &str) -> Result<FullViewingKey> {
-    if let Some(spk) = to_sk(key)? {
+pub fn to_fvk(
+    network: Network,
+    key: &str,
+    account: u32,
+    passphrase: &str,
+) -> Result<FullViewingKey> {
+    if let Some(spk) = to_sk(network, key, account, passphrase)? {
         return Ok(FullViewingKey::from(&spk));
     } else {
-        let (_, ufvk) = unified::Ufvk::decode(key)?;
+        let (ufvk_network, ufvk) = unified::Ufvk::decode(key)?;
+        if Network::from(ufvk_network) != network {
+            anyhow::bail!("UFVK is for a different network");
+        }
         for fvk in ufvk.items() {
             if let Fvk::Orchard(fvk) = fvk {
                 let fvk = FullViewingKey::from_bytes(&fvk).unwrap();
@@ -37,6 +49,40 @@ pub fn to_fvk(key: &str) -> Result<FullViewingKey> {
     anyhow::bail!("UFVK does not have an Orchard receiver");
 }
 
+/// Viewing key of a candidate, either a UFVK with an Orchard receiver
+/// or a hex encoded Orchard full viewing key
+pub fn to_candidate_fvk(network: Network, key: &str) -> Result<FullViewingKey> {
+    let key = key.trim();
+    if let Ok(bytes) = hex::decode(key) {
+        let bytes: [u8; 96] = bytes
+            .try_into()
+            .map_err(|_| anyhow!("Invalid Orchard viewing key length"))?;
+        return FullViewingKey::from_bytes(&bytes)
+            .ok_or(anyhow!("Invalid Orchard viewing key"));
+    }
+    if Mnemonic::from_phrase(key).is_ok() {
+        anyhow::bail!("Candidate keys must be viewing keys");
+    }
+    to_fvk(network, key, 0, "")
+}
+
+/// Viewing keys of every account to scan
+/// A UFVK has a single account, reported as account 0
+pub fn to_fvks(
+    network: Network,
+    key: &str,
+    accounts: &[u32],
+    passphrase: &str,
+) -> Result<Vec<(u32, FullViewingKey)>> {
+    if Mnemonic::from_phrase(key).is_err() {
+        return Ok(vec![(0, to_fvk(network, key, 0, passphrase)?)]);
+    }
+    accounts
+        .iter()
+        .map(|&account| Ok((account, to_fvk(network, key, account, passphrase)?)))
+        .collect()
+}
+
 pub fn try_decrypt(
     ivk: &PreparedIncomingViewingKey,
     action: &CompactOrchardAction,
@@ -59,3 +105,76 @@ pub fn try_decrypt(
     let note = try_compact_note_decryption(&domain, ivk, &action).map(|na| na.0);
     Ok(note)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phrase() -> String {
+        Mnemonic::generate(bip0039::Count::Words24)
+            .phrase()
+            .to_string()
+    }
+
+    fn ufvk(network: zcash_address::Network, fvk: &FullViewingKey) -> String {
+        unified::Ufvk::try_from_items(vec![Fvk::Orchard(fvk.to_bytes())])
+            .unwrap()
+            .encode(&network)
+    }
+
+    #[test]
+    fn seeds_derive_one_key_per_account() {
+        let phrase = phrase();
+        let fvks = to_fvks(Network::Main, &phrase, &[0, 1, 5], "").unwrap();
+        assert_eq!(fvks.iter().map(|(a, _)| *a).collect::<Vec<_>>(), [0, 1, 5]);
+        for (account, fvk) in fvks.iter() {
+            let expected = to_fvk(Network::Main, &phrase, *account, "").unwrap();
+            assert_eq!(fvk.to_bytes(), expected.to_bytes());
+        }
+        assert_ne!(fvks[0].1.to_bytes(), fvks[1].1.to_bytes());
+        assert_ne!(fvks[1].1.to_bytes(), fvks[2].1.to_bytes());
+
+        // the coin type is part of the derivation path
+        let test = to_fvk(Network::Test, &phrase, 0, "").unwrap();
+        assert_ne!(test.to_bytes(), fvks[0].1.to_bytes());
+    }
+
+    #[test]
+    fn passphrases_change_the_keys() {
+        let phrase = phrase();
+        let plain = to_fvk(Network::Main, &phrase, 0, "").unwrap();
+        let protected = to_fvk(Network::Main, &phrase, 0, "secret").unwrap();
+        assert_ne!(plain.to_bytes(), protected.to_bytes());
+        let again = to_fvk(Network::Main, &phrase, 0, "secret").unwrap();
+        assert_eq!(again.to_bytes(), protected.to_bytes());
+    }
+
+    #[test]
+    fn viewing_keys_have_a_single_account() {
+        let fvk = to_fvk(Network::Main, &phrase(), 3, "").unwrap();
+        let key = ufvk(zcash_address::Network::Main, &fvk);
+        let fvks = to_fvks(Network::Main, &key, &[1, 2], "ignored").unwrap();
+        assert_eq!(fvks.len(), 1);
+        assert_eq!(fvks[0].0, 0);
+        assert_eq!(fvks[0].1.to_bytes(), fvk.to_bytes());
+        assert!(to_fvks(Network::Test, &key, &[0], "").is_err());
+    }
+
+    #[test]
+    fn candidates_give_viewing_keys() {
+        let phrase = phrase();
+        let fvk = to_fvk(Network::Main, &phrase, 0, "").unwrap();
+        let hex_key = hex::encode(fvk.to_bytes());
+        let parsed = to_candidate_fvk(Network::Main, &format!(" {hex_key}\n")).unwrap();
+        assert_eq!(parsed.to_bytes(), fvk.to_bytes());
+        let key = ufvk(zcash_address::Network::Main, &fvk);
+        assert_eq!(
+            to_candidate_fvk(Network::Main, &key).unwrap().to_bytes(),
+            fvk.to_bytes()
+        );
+
+        assert!(to_candidate_fvk(Network::Main, &phrase).is_err());
+        assert!(to_candidate_fvk(Network::Main, &hex_key[2..]).is_err());
+        assert!(to_candidate_fvk(Network::Test, &key).is_err());
+    }
+}
@@ -17,19 +17,26 @@ use crate::{
     PoolConnection, Result,
 };
 
+/// Scan the blocks after the start height up to the end height for the
+/// notes of the keys and the nullifiers and commitments of the snapshot.
+/// Full balance elections start before the Orchard activation, so every
+/// Orchard block is scanned, see [`crate::election::SnapshotMode::FullBalance`]
 pub async fn download_reference_data(
     connection: PoolConnection,
     id_election: u32,
     election: &Election,
-    fvk: Option<FullViewingKey>,
+    fvks: &[(u32, FullViewingKey)],
     scope: Scope,
     lwd_url: &str,
     progress: impl Fn(u32) + Send + 'static,
 ) -> Result<(PoolConnection, u32)> {
-    let pivk = fvk.clone().map(|fvk| {
-        let ivk = fvk.to_ivk(scope);
-        PreparedIncomingViewingKey::new(&ivk)
-    });
+    let keys = fvks
+        .iter()
+        .map(|(account, fvk)| {
+            let ivk = fvk.to_ivk(scope);
+            (*account, fvk.clone(), PreparedIncomingViewingKey::new(&ivk))
+        })
+        .collect::<Vec<_>>();
     let domain = election.domain();
     let start = election.start_height as u64;
     let end = election.end_height as u64;
@@ -69,8 +76,7 @@ pub async fn download_reference_data(
                 &connection,
                 id_election,
                 domain,
-                fvk.as_ref(),
-                pivk.as_ref(),
+                &keys,
                 position,
                 block,
                 &mut nfs_cache,
@@ -105,8 +111,7 @@ fn handle_block(
     connection: &Connection,
     id_election: u32,
     domain: Fp,
-    fvk: Option<&FullViewingKey>,
-    pivk: Option<&PreparedIncomingViewingKey>,
+    keys: &[(u32, FullViewingKey, PreparedIncomingViewingKey)],
     start_position: usize,
     block: CompactBlock,
     nfs_cache: &mut HashMap<[u8; 32], u32>,
@@ -117,15 +122,15 @@ fn handle_block(
     let mut position = 0usize;
     for tx in block.vtx {
         for a in tx.actions {
-            if let Some(pivk) = pivk {
+            for (account, fvk, pivk) in keys.iter() {
                 if let Some(note) = try_decrypt(pivk, &a)? {
-                    let fvk = fvk.unwrap(); // if we have pivk, we have fvk
                     let p = start_position + position;
                     let height = block.height;
                     let txid = &tx.hash;
                     let id = store_note(
                         connection,
-                        0,
+                        id_election,
+                        *account,
                         domain,
                         fvk,
                         height as u32,
@@ -134,6 +139,7 @@ fn handle_block(
                         &note,
                     )?;
                     nfs_cache.insert(note.nullifier(fvk).to_bytes(), id);
+                    break;
                 }
             }
             let nf = &a.nullifier;
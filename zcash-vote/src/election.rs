@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use orchard::{
     vote::{Circuit, Frontier, OrchardHash, ProvingKey, VerifyingKey},
     Address,
@@ -7,35 +9,136 @@ use pasta_curves::group::ff::PrimeField as _;
 use prost::Message;
 use serde::{Deserialize, Serialize};
 
-use crate::{address::VoteAddress, errors::VoteError, pb::{self, Candidate}};
+use crate::{
+    address::VoteAddress,
+    errors::VoteError,
+    network::Network,
+    organizer::ElectionSignature,
+    pb::{self, Candidate},
+};
+
+/// Format of new elections. Version 1 elections do not commit
+/// to the snapshot roots in their domain
+pub const ELECTION_VERSION: u32 = 2;
+
+fn v1() -> u32 {
+    1
+}
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct CandidateChoice {
     pub address: String,
     pub choice: String,
 }
 
 impl CandidateChoice {
-    pub fn new(address: Address, choice: &str) -> Self {
+    pub fn new(network: Network, address: Address, choice: &str) -> Self {
         CandidateChoice {
-            address: VoteAddress(address).to_string(),
+            address: VoteAddress(address, network).to_string(),
             choice: choice.to_string(),
         }
     }
 }
 
+/// Which notes give voting power
+#[derive(Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotMode {
+    /// Notes received between the start and end heights
+    /// and unspent at the end height
+    #[default]
+    Range,
+    /// Every note unspent at the end height. The snapshot starts
+    /// at the Orchard activation and the start height is set to
+    /// the block before it
+    ///
+    /// Every Orchard block is scanned on purpose. A tree state
+    /// checkpoint at the start height would be the empty tree, and
+    /// the notes and the nullifiers that spend them can be anywhere
+    /// after the activation
+    FullBalance,
+}
+
+impl SnapshotMode {
+    fn to_pb(self) -> u32 {
+        match self {
+            SnapshotMode::Range => 0,
+            SnapshotMode::FullBalance => 1,
+        }
+    }
+}
+
+/// One question of a multi-question election
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Question {
+    pub question: String,
+    pub candidates: Vec<CandidateChoice>,
+}
+
+/// Link from the election of a question to its multi-question election
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ParentElection {
+    pub id: String,
+    pub index: u32,
+}
+
 /// Details of an election, including metadata, candidates, and election parameters.
-#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Election {
+    #[serde(default = "v1")]
+    pub version: u32,
     pub name: String,
     pub start_height: u32,
     pub end_height: u32,
     pub question: String,
     pub candidates: Vec<CandidateChoice>,
     pub signature_required: bool,
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub cmx: OrchardHash,
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub nf: OrchardHash,
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
     pub cmx_frontier: Option<Frontier>,
+    #[serde(default)]
+    pub network: Network,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ElectionSignature>,
+    /// When not empty, `question` and `candidates` are unused and
+    /// every question is voted on as a separate election
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub questions: Vec<Question>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<ParentElection>,
+    #[serde(default)]
+    pub snapshot: SnapshotMode,
+}
+
+/// Same as an election definition without a version
+impl Default for Election {
+    fn default() -> Self {
+        Election {
+            version: v1(),
+            name: String::new(),
+            start_height: 0,
+            end_height: 0,
+            question: String::new(),
+            candidates: vec![],
+            signature_required: false,
+            cmx: OrchardHash::default(),
+            nf: OrchardHash::default(),
+            cmx_frontier: None,
+            network: Network::default(),
+            signature: None,
+            questions: vec![],
+            parent: None,
+            snapshot: SnapshotMode::default(),
+        }
+    }
 }
 
 impl Election {
@@ -45,31 +148,444 @@ impl Election {
         Ok(election)
     }
 
+    /// Check that the election definition is complete and consistent:
+    /// - the snapshot heights are ordered and after the Orchard activation,
+    /// - the candidates have distinct choices and valid addresses for the network,
+    /// - the commitment frontier matches the commitment root
+    pub fn validate(&self) -> Result<(), VoteError> {
+        let invalid = |m: String| Err(VoteError::InvalidElection(m));
+        if self.version == 0 || self.version > ELECTION_VERSION {
+            return invalid(format!("unsupported version {}", self.version));
+        }
+        let activation_height = self.network.orchard_activation_height();
+        match self.snapshot {
+            SnapshotMode::Range if self.start_height < activation_height => {
+                return invalid(format!(
+                    "start height {} is before the Orchard activation height {activation_height}",
+                    self.start_height
+                ));
+            }
+            SnapshotMode::FullBalance if self.version < 2 => {
+                return invalid("full balance snapshots require version 2".to_string());
+            }
+            SnapshotMode::FullBalance if self.start_height != activation_height - 1 => {
+                return invalid(format!(
+                    "full balance snapshots must start at height {}",
+                    activation_height - 1
+                ));
+            }
+            _ => {}
+        }
+        if self.start_height >= self.end_height {
+            return invalid(format!(
+                "start height {} must be before end height {}",
+                self.start_height, self.end_height
+            ));
+        }
+
+        if self.version < 2 && (!self.questions.is_empty() || self.parent.is_some()) {
+            return invalid("multiple questions require version 2".to_string());
+        }
+        if !self.questions.is_empty() && self.parent.is_some() {
+            return invalid("questions cannot be nested".to_string());
+        }
+
+        // addresses must be distinct across questions too,
+        // because a vote is decrypted by the candidate key
+        let mut addresses = HashSet::new();
+        for (q, question) in self.questions().iter().enumerate() {
+            if question.candidates.is_empty() {
+                return invalid(format!("no candidates for question #{q}"));
+            }
+            let mut choices = HashSet::new();
+            for (i, c) in question.candidates.iter().enumerate() {
+                if c.choice.trim().is_empty() {
+                    return invalid(format!("candidate #{i} has no choice"));
+                }
+                if !choices.insert(c.choice.as_str()) {
+                    return invalid(format!("duplicate choice {}", c.choice));
+                }
+                if let Err(e) = VoteAddress::decode_for(self.network, &c.address) {
+                    return invalid(format!("candidate #{i}: {e}"));
+                }
+                if !addresses.insert(c.address.as_str()) {
+                    return invalid(format!("duplicate address {}", c.address));
+                }
+            }
+        }
+
+        if self.cmx.0 == [0u8; 32] {
+            return invalid("missing cmx root".to_string());
+        }
+        if self.nf.0 == [0u8; 32] {
+            return invalid("missing nf root".to_string());
+        }
+        let Some(frontier) = self.cmx_frontier.as_ref() else {
+            return invalid("missing cmx frontier".to_string());
+        };
+        if frontier.root() != self.cmx.0 {
+            return invalid("cmx frontier does not match the cmx root".to_string());
+        }
+        Ok(())
+    }
+
+    /// Questions of the election, a single question election
+    /// has its question and candidates at the top level
+    pub fn questions(&self) -> Vec<Question> {
+        if self.questions.is_empty() {
+            vec![Question {
+                question: self.question.clone(),
+                candidates: self.candidates.clone(),
+            }]
+        } else {
+            self.questions.clone()
+        }
+    }
+
+    /// Elections that ballots are cast on, one per question
+    ///
+    /// Questions of the same election share the snapshot but keep
+    /// their own ballots, nullifiers and commitment tree. Therefore
+    /// the same notes can vote on every question
+    pub fn question_elections(&self) -> Vec<Election> {
+        if self.questions.is_empty() {
+            return vec![self.clone()];
+        }
+        let id = self.id();
+        let n = self.questions.len();
+        self.questions
+            .iter()
+            .enumerate()
+            .map(|(i, q)| Election {
+                name: format!("{} ({}/{n})", self.name, i + 1),
+                question: q.question.clone(),
+                candidates: q.candidates.clone(),
+                signature: None,
+                questions: vec![],
+                parent: Some(ParentElection {
+                    id: id.clone(),
+                    index: i as u32,
+                }),
+                ..self.clone()
+            })
+            .collect()
+    }
+
     pub fn id(&self) -> String {
         hex::encode(self.domain().to_repr())
     }
 
     pub fn domain(&self) -> Fp {
-        let election_params = pb::Election {
+        let election_params = match self.version {
+            0 | 1 => self.to_pb_v1().encode_to_vec(),
+            _ => self.to_pb_v2().encode_to_vec(),
+        };
+
+        orchard::vote::calculate_domain(&election_params)
+    }
+
+    fn pb_candidates(&self) -> Vec<Candidate> {
+        to_pb_candidates(&self.candidates)
+    }
+
+    fn to_pb_v1(&self) -> pb::Election {
+        pb::Election {
             name: self.name.clone(),
             start_height: self.start_height,
             end_height: self.end_height,
             question: self.question.clone(),
-            candidates: self.candidates.iter().map(|c|
-                Candidate {
-                    address: c.address.clone(),
-                    choice: c.choice.clone(),
-                }
-            ).collect(),
+            candidates: self.pb_candidates(),
             signature_required: self.signature_required,
-        };
-        let election_params = election_params.encode_to_vec();
+        }
+    }
 
-        orchard::vote::calculate_domain(&election_params)
+    fn to_pb_v2(&self) -> pb::ElectionV2 {
+        pb::ElectionV2 {
+            version: self.version,
+            name: self.name.clone(),
+            start_height: self.start_height,
+            end_height: self.end_height,
+            question: self.question.clone(),
+            candidates: self.pb_candidates(),
+            signature_required: self.signature_required,
+            cmx: self.cmx.0.to_vec(),
+            nf: self.nf.0.to_vec(),
+            cmx_frontier: self.cmx_frontier.as_ref().map(|f| pb::Frontier {
+                position: f.position as u64,
+                leaf: f.leaf.0.to_vec(),
+                ommers: f.ommers.iter().map(|o| o.0.to_vec()).collect(),
+            }),
+            questions: self.questions.iter().map(|q| pb::Question {
+                question: q.question.clone(),
+                candidates: to_pb_candidates(&q.candidates),
+            }).collect(),
+            parent: self.parent.as_ref().map(|p| p.id.clone()).unwrap_or_default(),
+            question_index: self.parent.as_ref().map(|p| p.index).unwrap_or_default(),
+            snapshot_mode: self.snapshot.to_pb(),
+            network: self.network.to_pb(),
+        }
     }
 }
 
+/// URL of another election of the server that hosts `url`.
+/// Election URLs end with the election id
+pub fn election_url(url: &str, id: &str) -> String {
+    match url.trim_end_matches('/').rsplit_once('/') {
+        Some((base, _)) => format!("{base}/{id}"),
+        None => id.to_string(),
+    }
+}
+
+fn to_pb_candidates(candidates: &[CandidateChoice]) -> Vec<Candidate> {
+    candidates.iter().map(|c|
+        Candidate {
+            address: c.address.clone(),
+            choice: c.choice.clone(),
+        }
+    ).collect()
+}
+
 lazy_static::lazy_static! {
     pub static ref BALLOT_PK: ProvingKey<Circuit> = ProvingKey::build();
     pub static ref BALLOT_VK: VerifyingKey<Circuit> = VerifyingKey::build();
 }
+
+#[cfg(test)]
+mod tests {
+    use orchard::keys::{FullViewingKey, Scope, SpendingKey};
+
+    use super::*;
+
+    fn candidate(network: Network, i: u8, choice: &str) -> CandidateChoice {
+        let sk = SpendingKey::from_zip32_seed(&[i; 32], network.coin_type(), 0).unwrap();
+        let address = FullViewingKey::from(&sk).address_at(0u64, Scope::External);
+        CandidateChoice::new(network, address, choice)
+    }
+
+    fn valid() -> Election {
+        let frontier = Frontier {
+            position: 0,
+            leaf: OrchardHash([1; 32]),
+            ommers: vec![],
+        };
+        let start_height = Network::Main.orchard_activation_height();
+        Election {
+            version: ELECTION_VERSION,
+            name: "Valid".to_string(),
+            start_height,
+            end_height: start_height + 1000,
+            question: "Yes or no?".to_string(),
+            candidates: vec![
+                candidate(Network::Main, 1, "Yes"),
+                candidate(Network::Main, 2, "No"),
+            ],
+            cmx: OrchardHash(frontier.root()),
+            nf: OrchardHash([1; 32]),
+            cmx_frontier: Some(frontier),
+            ..Default::default()
+        }
+    }
+
+    fn multi_question() -> Election {
+        Election {
+            question: String::new(),
+            candidates: vec![],
+            questions: vec![
+                Question {
+                    question: "First?".to_string(),
+                    candidates: vec![
+                        candidate(Network::Main, 1, "Yes"),
+                        candidate(Network::Main, 2, "No"),
+                    ],
+                },
+                Question {
+                    question: "Second?".to_string(),
+                    candidates: vec![
+                        candidate(Network::Main, 3, "Yes"),
+                        candidate(Network::Main, 4, "No"),
+                    ],
+                },
+            ],
+            ..valid()
+        }
+    }
+
+    fn rejected(change: impl FnOnce(&mut Election)) -> bool {
+        let mut election = valid();
+        change(&mut election);
+        matches!(election.validate(), Err(VoteError::InvalidElection(_)))
+    }
+
+    #[test]
+    fn default_elections_are_version_1() {
+        let mut json = serde_json::to_value(Election::default()).unwrap();
+        json.as_object_mut().unwrap().remove("version");
+        let election: Election = serde_json::from_value(json).unwrap();
+        assert_eq!(election.version, Election::default().version);
+        assert_eq!(election.version, 1);
+    }
+
+    #[test]
+    fn v2_ids_commit_to_the_snapshot_and_network() {
+        let election = Election {
+            version: 2,
+            name: "Binding".to_string(),
+            ..Default::default()
+        };
+        let id = election.id();
+        let changes: [fn(&mut Election); 6] = [
+            |e| e.cmx = OrchardHash([1; 32]),
+            |e| e.nf = OrchardHash([1; 32]),
+            |e| {
+                e.cmx_frontier = Some(Frontier {
+                    position: 0,
+                    leaf: OrchardHash([1; 32]),
+                    ommers: vec![],
+                })
+            },
+            |e| e.network = Network::Test,
+            |e| e.snapshot = SnapshotMode::FullBalance,
+            |e| e.version = 1,
+        ];
+        for change in changes {
+            let mut changed = election.clone();
+            change(&mut changed);
+            assert_ne!(changed.id(), id);
+        }
+    }
+
+    #[test]
+    fn v1_ids_are_unchanged() {
+        let election = Election {
+            name: "Legacy".to_string(),
+            ..Default::default()
+        };
+        // v1 ids only cover the fields of the original format
+        let rooted = Election {
+            cmx: OrchardHash([1; 32]),
+            nf: OrchardHash([1; 32]),
+            network: Network::Test,
+            ..election.clone()
+        };
+        assert_eq!(rooted.id(), election.id());
+        let renamed = Election {
+            name: "Renamed".to_string(),
+            ..election.clone()
+        };
+        assert_ne!(renamed.id(), election.id());
+    }
+
+    #[test]
+    fn valid_elections_pass() {
+        valid().validate().unwrap();
+        multi_question().validate().unwrap();
+        Election {
+            version: 1,
+            ..valid()
+        }
+        .validate()
+        .unwrap();
+    }
+
+    #[test]
+    fn inconsistent_elections_are_rejected() {
+        assert!(rejected(|e| e.version = 0));
+        assert!(rejected(|e| e.version = ELECTION_VERSION + 1));
+        assert!(rejected(|e| e.start_height -= 1));
+        assert!(rejected(|e| e.end_height = e.start_height));
+        assert!(rejected(|e| e.candidates.clear()));
+        assert!(rejected(|e| e.candidates[1].choice = "Yes".to_string()));
+        assert!(rejected(|e| e.candidates[1].choice = " ".to_string()));
+        assert!(rejected(
+            |e| e.candidates[1].address = e.candidates[0].address.clone()
+        ));
+        assert!(rejected(
+            |e| e.candidates[1] = candidate(Network::Test, 2, "No")
+        ));
+        assert!(rejected(|e| e.nf = OrchardHash::default()));
+        assert!(rejected(|e| e.cmx_frontier = None));
+        assert!(rejected(|e| e.cmx = OrchardHash([2; 32])));
+    }
+
+    #[test]
+    fn questions_are_checked_together() {
+        let mut election = multi_question();
+        election.questions[1].candidates[0] = election.questions[0].candidates[0].clone();
+        assert!(election.validate().is_err());
+
+        let v1 = Election {
+            version: 1,
+            ..multi_question()
+        };
+        assert!(v1.validate().is_err());
+
+        let mut nested = multi_question();
+        nested.parent = Some(ParentElection {
+            id: valid().id(),
+            index: 0,
+        });
+        assert!(nested.validate().is_err());
+    }
+
+    #[test]
+    fn question_urls_replace_the_election_id() {
+        assert_eq!(
+            election_url("https://vote.example/election/abc", "def"),
+            "https://vote.example/election/def"
+        );
+        assert_eq!(
+            election_url("https://vote.example/election/abc/", "def"),
+            "https://vote.example/election/def"
+        );
+        assert_eq!(election_url("abc", "def"), "def");
+    }
+
+    #[test]
+    fn questions_are_voted_as_separate_elections() {
+        let single = valid();
+        let elections = single.question_elections();
+        assert_eq!(elections.len(), 1);
+        assert_eq!(elections[0].id(), single.id());
+
+        let election = multi_question();
+        let elections = election.question_elections();
+        assert_eq!(elections.len(), 2);
+        for (i, e) in elections.iter().enumerate() {
+            let parent = e.parent.as_ref().unwrap();
+            assert_eq!(
+                (parent.id.as_str(), parent.index),
+                (election.id().as_str(), i as u32)
+            );
+            assert_eq!(e.name, format!("Valid ({}/2)", i + 1));
+            assert_eq!(e.question, election.questions[i].question);
+            assert!(e.questions.is_empty() && e.signature.is_none());
+            assert_eq!((e.cmx.0, e.nf.0), (election.cmx.0, election.nf.0));
+            e.validate().unwrap();
+        }
+        assert_ne!(elections[0].id(), elections[1].id());
+    }
+
+    #[test]
+    fn full_balance_snapshots_start_at_the_activation() {
+        let activation = Network::Main.orchard_activation_height();
+        let full_balance = Election {
+            snapshot: SnapshotMode::FullBalance,
+            start_height: activation - 1,
+            ..valid()
+        };
+        full_balance.validate().unwrap();
+
+        let v1 = Election {
+            version: 1,
+            ..full_balance.clone()
+        };
+        assert!(v1.validate().is_err());
+        for start_height in [activation, activation + 100, 0] {
+            let shifted = Election {
+                start_height,
+                ..full_balance.clone()
+            };
+            assert!(shifted.validate().is_err());
+        }
+    }
+}
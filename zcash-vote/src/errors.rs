@@ -1,6 +1,7 @@
 use halo2_proofs::plonk::Error as PlonkError;
 use http::uri::InvalidUri;
 use rusqlite::Error as SqlError;
+use serde::{ser::SerializeStruct as _, Deserialize, Serialize, Serializer};
 use thiserror::Error;
 use tonic::{transport::Error as TonicTransportError, Status};
 
@@ -18,6 +19,8 @@ pub enum VoteError {
     TonicError(#[from] Status),
     #[error(transparent)]
     OrchardVoteError(#[from] orchard::vote::VoteError),
+    #[error(transparent)]
+    HttpError(#[from] reqwest::Error),
 
     #[error("Note at position {0} is out of range")]
     OutOfRange(usize),
@@ -27,7 +30,177 @@ pub enum VoteError {
     InvalidJson(String),
     #[error("Invalid Ballot: {0}")]
     InvalidBallot(String),
+    #[error("Invalid Election: {0}")]
+    InvalidElection(String),
+    #[error("Invalid Share: {0}")]
+    InvalidShare(String),
+    #[error("Invalid Signature: {0}")]
+    InvalidSignature(String),
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("Invalid {0} root")]
+    BadRoot(String),
+    #[error("Election is closed")]
+    ClosedElection,
+    #[error("Network error: {0}")]
+    Network(String),
+    /// The server is receiving more ballots than it can verify
+    #[error("Too many requests: {0}")]
+    RateLimited(String),
+    /// Error reported by a vote server
+    #[error("{}", .0.message)]
+    Remote(ErrorResponse),
 
     #[error(transparent)]
-    Anyhow(#[from] anyhow::Error),
+    Anyhow(anyhow::Error),
+}
+
+/// Stable, machine readable category of a `VoteError`
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    NotFound,
+    DoubleSpend,
+    BadRoot,
+    BadSignature,
+    ClosedElection,
+    Network,
+    Proof,
+    InvalidData,
+    Internal,
+    RateLimited,
+}
+
+impl ErrorCode {
+    pub fn http_status(&self) -> u16 {
+        match self {
+            ErrorCode::NotFound => 404,
+            ErrorCode::DoubleSpend => 409,
+            ErrorCode::ClosedElection => 403,
+            ErrorCode::BadRoot
+            | ErrorCode::BadSignature
+            | ErrorCode::Proof
+            | ErrorCode::InvalidData => 422,
+            ErrorCode::Network => 502,
+            ErrorCode::Internal => 500,
+            ErrorCode::RateLimited => 429,
+        }
+    }
+
+    /// Result code of ABCI responses, 0 is reserved for success
+    pub fn abci_code(&self) -> u32 {
+        match self {
+            ErrorCode::NotFound => 1,
+            ErrorCode::DoubleSpend => 2,
+            ErrorCode::BadRoot => 3,
+            ErrorCode::ClosedElection => 4,
+            ErrorCode::Network => 5,
+            ErrorCode::Proof => 6,
+            ErrorCode::InvalidData => 7,
+            ErrorCode::Internal => 8,
+            ErrorCode::BadSignature => 9,
+            ErrorCode::RateLimited => 10,
+        }
+    }
+
+    pub fn from_abci_code(code: u32) -> Self {
+        match code {
+            1 => ErrorCode::NotFound,
+            2 => ErrorCode::DoubleSpend,
+            3 => ErrorCode::BadRoot,
+            4 => ErrorCode::ClosedElection,
+            5 => ErrorCode::Network,
+            6 => ErrorCode::Proof,
+            7 => ErrorCode::InvalidData,
+            9 => ErrorCode::BadSignature,
+            10 => ErrorCode::RateLimited,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+impl VoteError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            VoteError::SqlError(SqlError::QueryReturnedNoRows) | VoteError::NotFound(_) => {
+                ErrorCode::NotFound
+            }
+            VoteError::DoubleNullifier(_) => ErrorCode::DoubleSpend,
+            VoteError::BadRoot(_) => ErrorCode::BadRoot,
+            VoteError::ClosedElection => ErrorCode::ClosedElection,
+            VoteError::Remote(e) => e.code,
+            VoteError::InvalidUri(_)
+            | VoteError::TonicTransportError(_)
+            | VoteError::TonicError(_)
+            | VoteError::HttpError(_)
+            | VoteError::Network(_) => ErrorCode::Network,
+            VoteError::PlonkError(_) | VoteError::OrchardVoteError(_) => ErrorCode::Proof,
+            VoteError::OutOfRange(_)
+            | VoteError::InvalidJson(_)
+            | VoteError::InvalidBallot(_)
+            | VoteError::InvalidElection(_)
+            | VoteError::InvalidShare(_) => ErrorCode::InvalidData,
+            VoteError::InvalidSignature(_) => ErrorCode::BadSignature,
+            VoteError::RateLimited(_) => ErrorCode::RateLimited,
+            VoteError::SqlError(_) | VoteError::Anyhow(_) => ErrorCode::Internal,
+        }
+    }
+}
+
+/// Recover the original error when it was wrapped by anyhow
+/// so that it keeps its error code
+impl From<anyhow::Error> for VoteError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<VoteError>() {
+            Ok(e) => return e,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<SqlError>() {
+            Ok(e) => return VoteError::SqlError(e),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<reqwest::Error>() {
+            Ok(e) => return VoteError::HttpError(e),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<serde_json::Error>() {
+            Ok(e) => return VoteError::InvalidJson(e.to_string()),
+            Err(err) => err,
+        };
+        VoteError::Anyhow(err)
+    }
+}
+
+/// Errors serialize as `{ "code": ..., "message": ... }`
+impl Serialize for VoteError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("VoteError", 2)?;
+        s.serialize_field("code", &self.code())?;
+        s.serialize_field("message", &self.to_string())?;
+        s.end()
+    }
+}
+
+/// Serialized form of a `VoteError`, as received by clients
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl From<&VoteError> for ErrorResponse {
+    fn from(err: &VoteError) -> Self {
+        ErrorResponse {
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<VoteError> for ErrorResponse {
+    fn from(err: VoteError) -> Self {
+        ErrorResponse::from(&err)
+    }
 }
@@ -16,10 +16,15 @@ pub type PoolConnection = PooledConnection<SqliteConnectionManager>;
 
 pub mod pb;
 pub mod address;
+pub mod client;
 pub mod db;
 pub mod decrypt;
 pub mod download;
 pub mod election;
+pub mod migration;
+pub mod network;
+pub mod organizer;
+pub mod shares;
 pub mod trees;
 pub mod validate;
 
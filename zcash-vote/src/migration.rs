@@ -0,0 +1,109 @@
+use anyhow::Result;
+use rusqlite::Connection;
+
+use crate::db::{load_prop, store_prop};
+
+/// Upgrade step from version `i` to `i + 1`, where `i` is its index
+/// in the list of migrations
+pub type Migration = fn(&Connection) -> Result<()>;
+
+/// Property that holds the version of the base schema
+pub const SCHEMA_VERSION: &str = "schema_version";
+
+/// Schema of the databases created before the versioned migrations.
+/// The migrations must upgrade it with its data
+pub const BASELINE_SCHEMA: &str = "
+    CREATE TABLE properties(
+    id_property INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    value TEXT NOT NULL);
+    CREATE TABLE ballots(
+    id_ballot INTEGER PRIMARY KEY,
+    election INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    hash BLOB NOT NULL UNIQUE,
+    data BLOB NOT NULL);
+    CREATE TABLE nfs(
+    id_nf INTEGER PRIMARY KEY NOT NULL,
+    election INTEGER NOT NULL,
+    hash BLOB NOT NULL UNIQUE);
+    CREATE TABLE dnfs(
+    id_dnf INTEGER PRIMARY KEY NOT NULL,
+    election INTEGER NOT NULL,
+    hash BLOB NOT NULL UNIQUE);
+    CREATE TABLE cmxs(
+    id_cmx INTEGER PRIMARY KEY NOT NULL,
+    election INTEGER NOT NULL,
+    hash BLOB NOT NULL UNIQUE);
+    CREATE TABLE cmx_roots(
+    id_cmx_root INTEGER PRIMARY KEY,
+    election INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    hash BLOB NOT NULL,
+    CONSTRAINT u_cmx_roots UNIQUE (election, hash));
+    CREATE TABLE cmx_frontiers(
+    id_cmx_frontier INTEGER PRIMARY KEY,
+    election INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    frontier TEXT NOT NULL,
+    CONSTRAINT u_cmx_frontiers UNIQUE (election, height));
+    CREATE TABLE notes(
+    id_note INTEGER PRIMARY KEY,
+    election INTEGER NOT NULL,
+    position INTEGER NOT NULL UNIQUE,
+    height INTEGER NOT NULL,
+    txid BLOB NOT NULL,
+    value INTEGER NOT NULL,
+    div BLOB NOT NULL,
+    rseed BLOB NOT NULL,
+    nf BLOB NOT NULL,
+    dnf BLOB NOT NULL,
+    rho BLOB NOT NULL,
+    spent INTEGER);";
+
+pub fn get_schema_version(connection: &Connection, version_property: &str) -> Result<u32> {
+    create_properties(connection)?;
+    let version = load_prop(connection, version_property)?
+        .map(|v| v.parse::<u32>())
+        .transpose()?
+        .unwrap_or_default();
+    Ok(version)
+}
+
+/// Apply the migrations that have not run yet, in order
+/// Each step runs in its own transaction together with the
+/// update of the version property
+pub fn migrate(
+    connection: &Connection,
+    version_property: &str,
+    migrations: &[Migration],
+) -> Result<()> {
+    let version = get_schema_version(connection, version_property)? as usize;
+    if version > migrations.len() {
+        anyhow::bail!(
+            "Database {version_property} {version} is newer than this application ({})",
+            migrations.len()
+        );
+    }
+    for (i, migration) in migrations.iter().enumerate().skip(version) {
+        log::info!("Migrating {version_property} to {}", i + 1);
+        let transaction = connection.unchecked_transaction()?;
+        migration(&transaction)?;
+        store_prop(&transaction, version_property, &(i + 1).to_string())?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+/// The properties table holds the schema versions, therefore
+/// it is created outside of the migrations
+fn create_properties(connection: &Connection) -> Result<()> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS properties(
+        id_property INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        value TEXT NOT NULL)",
+        [],
+    )?;
+    Ok(())
+}
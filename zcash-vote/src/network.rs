@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+use zcash_primitives::{
+    consensus::{MainNetwork, NetworkUpgrade, Parameters, TestNetwork},
+    constants::{mainnet, regtest, testnet},
+};
+
+/// Zcash network an election takes its snapshot from
+#[derive(Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Main,
+    Test,
+    Regtest,
+}
+
+impl Network {
+    /// Main is the default value of the field, so that it
+    /// leaves the encoding unchanged
+    pub(crate) fn to_pb(self) -> u32 {
+        match self {
+            Network::Main => 0,
+            Network::Test => 1,
+            Network::Regtest => 2,
+        }
+    }
+
+    pub fn coin_type(&self) -> u32 {
+        match self {
+            Network::Main => mainnet::COIN_TYPE,
+            Network::Test => testnet::COIN_TYPE,
+            Network::Regtest => regtest::COIN_TYPE,
+        }
+    }
+
+    /// First block where Orchard notes can exist. Regtest networks
+    /// are expected to activate every upgrade at height 1
+    pub fn orchard_activation_height(&self) -> u32 {
+        let height = match self {
+            Network::Main => MainNetwork.activation_height(NetworkUpgrade::Nu5),
+            Network::Test => TestNetwork.activation_height(NetworkUpgrade::Nu5),
+            Network::Regtest => None,
+        };
+        height.map(u32::from).unwrap_or(1)
+    }
+
+    pub fn vote_hrp(&self) -> &'static str {
+        match self {
+            Network::Main => "zvote",
+            Network::Test => "zvotetest",
+            Network::Regtest => "zvoteregtest",
+        }
+    }
+
+    pub fn from_vote_hrp(hrp: &str) -> Option<Self> {
+        [Network::Main, Network::Test, Network::Regtest]
+            .into_iter()
+            .find(|n| n.vote_hrp() == hrp)
+    }
+
+    /// Default lightwalletd server, overridden by the LWD_URL env variable
+    pub fn lwd_url(&self) -> String {
+        if let Ok(url) = std::env::var("LWD_URL") {
+            return url;
+        }
+        match self {
+            Network::Main => "https://zec.rocks",
+            Network::Test => "https://testnet.zec.rocks",
+            Network::Regtest => "http://127.0.0.1:9067",
+        }
+        .to_string()
+    }
+}
+
+impl From<zcash_address::Network> for Network {
+    fn from(network: zcash_address::Network) -> Self {
+        match network {
+            zcash_address::Network::Main => Network::Main,
+            zcash_address::Network::Test => Network::Test,
+            zcash_address::Network::Regtest => Network::Regtest,
+        }
+    }
+}
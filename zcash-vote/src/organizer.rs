@@ -0,0 +1,186 @@
+use ed25519_dalek::{Signature, Signer as _, SigningKey, Verifier as _, VerifyingKey};
+use pasta_curves::group::ff::PrimeField as _;
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+
+use crate::{election::Election, errors::VoteError};
+
+/// Prefix of the signed message, so that an organizer key
+/// cannot be tricked into signing something else
+const ORGANIZER_PERSONALIZATION: &[u8] = b"ZcashVote_Organizer";
+
+/// Ed25519 signature of the election id by the organizer
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ElectionSignature {
+    pub organizer_pubkey: String,
+    pub signature: String,
+}
+
+/// Long term key of an election organizer, hex encoded
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct OrganizerKey {
+    pub secret_key: String,
+    pub public_key: String,
+}
+
+impl OrganizerKey {
+    pub fn generate() -> Self {
+        let sk = SigningKey::generate(&mut OsRng);
+        OrganizerKey {
+            secret_key: hex::encode(sk.to_bytes()),
+            public_key: hex::encode(sk.verifying_key().to_bytes()),
+        }
+    }
+}
+
+/// Version 1 ids only cover the name, heights and candidates, not the
+/// snapshot roots, network and questions, so they cannot be signed
+fn check_version(election: &Election) -> Result<(), VoteError> {
+    if election.version < 2 {
+        return Err(VoteError::InvalidSignature(format!(
+            "Version {} elections cannot be signed",
+            election.version
+        )));
+    }
+    Ok(())
+}
+
+fn signed_message(election: &Election) -> Vec<u8> {
+    let mut message = ORGANIZER_PERSONALIZATION.to_vec();
+    message.extend_from_slice(&election.domain().to_repr());
+    message
+}
+
+fn parse_key<const N: usize>(key: &str, name: &str) -> Result<[u8; N], VoteError> {
+    let key = hex::decode(key.trim())
+        .map_err(|_| VoteError::InvalidSignature(format!("{name} is not hex")))?;
+    key.try_into()
+        .map_err(|_| VoteError::InvalidSignature(format!("{name} has an invalid length")))
+}
+
+pub fn verifying_key(public_key: &str) -> Result<VerifyingKey, VoteError> {
+    let key = parse_key::<32>(public_key, "Organizer public key")?;
+    VerifyingKey::from_bytes(&key)
+        .map_err(|_| VoteError::InvalidSignature("Invalid organizer public key".to_string()))
+}
+
+/// Sign the election id and attach the signature to the election
+pub fn sign_election(election: &mut Election, secret_key: &str) -> Result<(), VoteError> {
+    check_version(election)?;
+    let sk = SigningKey::from_bytes(&parse_key::<32>(secret_key, "Organizer secret key")?);
+    let signature = sk.sign(&signed_message(election));
+    election.signature = Some(ElectionSignature {
+        organizer_pubkey: hex::encode(sk.verifying_key().to_bytes()),
+        signature: hex::encode(signature.to_bytes()),
+    });
+    Ok(())
+}
+
+/// Check the organizer signature of an election
+/// When a key is pinned, the election must be signed by this key
+/// Otherwise, unsigned elections are accepted
+/// Signed elections must be version 2 or later
+pub fn verify_election(election: &Election, pinned_key: Option<&str>) -> Result<(), VoteError> {
+    let Some(signature) = election.signature.as_ref() else {
+        if pinned_key.is_some() {
+            return Err(VoteError::InvalidSignature(
+                "Election is not signed by the organizer".to_string(),
+            ));
+        }
+        return Ok(());
+    };
+    check_version(election)?;
+    let pk = verifying_key(&signature.organizer_pubkey)?;
+    if let Some(pinned_key) = pinned_key {
+        if verifying_key(pinned_key)? != pk {
+            return Err(VoteError::InvalidSignature(
+                "Election is signed by another organizer".to_string(),
+            ));
+        }
+    }
+    let sig = Signature::from_bytes(&parse_key::<64>(&signature.signature, "Signature")?);
+    pk.verify(&signed_message(election), &sig)
+        .map_err(|_| VoteError::InvalidSignature("Invalid organizer signature".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use orchard::vote::OrchardHash;
+
+    use super::*;
+    use crate::{election::ELECTION_VERSION, network::Network};
+
+    fn signed(key: &OrganizerKey) -> Election {
+        let mut election = Election {
+            version: ELECTION_VERSION,
+            name: "Signed".to_string(),
+            start_height: 2_000_000,
+            end_height: 2_100_000,
+            ..Default::default()
+        };
+        sign_election(&mut election, &key.secret_key).unwrap();
+        election
+    }
+
+    #[test]
+    fn signatures_round_trip() {
+        let key = OrganizerKey::generate();
+        let election = signed(&key);
+        assert_eq!(
+            election.signature.as_ref().unwrap().organizer_pubkey,
+            key.public_key
+        );
+        verify_election(&election, None).unwrap();
+        verify_election(&election, Some(&key.public_key)).unwrap();
+    }
+
+    #[test]
+    fn tampered_elections_are_rejected() {
+        let election = signed(&OrganizerKey::generate());
+        let mut tampered = election.clone();
+        tampered.cmx = OrchardHash([1; 32]);
+        assert!(verify_election(&tampered, None).is_err());
+        let mut tampered = election.clone();
+        tampered.network = Network::Test;
+        assert!(verify_election(&tampered, None).is_err());
+        let mut tampered = election;
+        tampered.end_height += 1;
+        assert!(verify_election(&tampered, None).is_err());
+    }
+
+    #[test]
+    fn pinned_keys_require_their_signature() {
+        let key = OrganizerKey::generate();
+        let other = OrganizerKey::generate();
+        let election = signed(&key);
+        assert!(verify_election(&election, Some(&other.public_key)).is_err());
+
+        let unsigned = Election {
+            signature: None,
+            ..election
+        };
+        verify_election(&unsigned, None).unwrap();
+        assert!(verify_election(&unsigned, Some(&key.public_key)).is_err());
+    }
+
+    #[test]
+    fn version_1_elections_cannot_be_signed() {
+        let key = OrganizerKey::generate();
+        let mut election = Election {
+            version: 1,
+            ..Default::default()
+        };
+        assert!(sign_election(&mut election, &key.secret_key).is_err());
+        verify_election(&election, None).unwrap();
+        assert!(verify_election(&election, Some(&key.public_key)).is_err());
+
+        // a v2 signature does not carry over to the v1 id
+        let downgraded = Election {
+            version: 1,
+            ..signed(&key)
+        };
+        assert!(verify_election(&downgraded, None).is_err());
+        assert!(verify_election(&downgraded, Some(&key.public_key)).is_err());
+    }
+}
@@ -20,3 +20,56 @@ pub struct Election {
     #[prost(bool, tag="6")]
     pub signature_required: bool,
 }
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Frontier {
+    #[prost(uint64, tag="1")]
+    pub position: u64,
+    #[prost(bytes="vec", tag="2")]
+    pub leaf: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes="vec", repeated, tag="3")]
+    pub ommers: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+}
+/// Version 2 also commits to the eligibility snapshot
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ElectionV2 {
+    #[prost(uint32, tag="1")]
+    pub version: u32,
+    #[prost(string, tag="2")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(uint32, tag="3")]
+    pub start_height: u32,
+    #[prost(uint32, tag="4")]
+    pub end_height: u32,
+    #[prost(string, tag="5")]
+    pub question: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag="6")]
+    pub candidates: ::prost::alloc::vec::Vec<Candidate>,
+    #[prost(bool, tag="7")]
+    pub signature_required: bool,
+    #[prost(bytes="vec", tag="8")]
+    pub cmx: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes="vec", tag="9")]
+    pub nf: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag="10")]
+    pub cmx_frontier: ::core::option::Option<Frontier>,
+    #[prost(message, repeated, tag="11")]
+    pub questions: ::prost::alloc::vec::Vec<Question>,
+    /// id of the multi-question election and index of the question
+    #[prost(string, tag="12")]
+    pub parent: ::prost::alloc::string::String,
+    #[prost(uint32, tag="13")]
+    pub question_index: u32,
+    /// 0: notes received in the height range, 1: full balance
+    #[prost(uint32, tag="14")]
+    pub snapshot_mode: u32,
+    /// 0: main, 1: test, 2: regtest
+    #[prost(uint32, tag="15")]
+    pub network: u32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Question {
+    #[prost(string, tag="1")]
+    pub question: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag="2")]
+    pub candidates: ::prost::alloc::vec::Vec<Candidate>,
+}
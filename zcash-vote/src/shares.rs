@@ -0,0 +1,266 @@
+use bech32::{FromBase32, ToBase32, Variant};
+use bip0039::Mnemonic;
+use blake2b_simd::Params;
+use rand_core::{OsRng, RngCore};
+
+use crate::errors::VoteError;
+
+const SHARE_HRP: &str = "zvoteshare";
+const SHARE_VERSION: u8 = 1;
+const FINGERPRINT_LEN: usize = 8;
+
+/// Share of a 32 byte secret, in a k-of-n Shamir scheme over GF(256)
+///
+/// Shares are bech32m encoded, the checksum detects corrupted shares
+/// and the fingerprint of the secret detects shares that belong to
+/// different secrets
+#[derive(Clone, Debug)]
+pub struct Share {
+    pub threshold: u8,
+    pub index: u8,
+    pub fingerprint: [u8; FINGERPRINT_LEN],
+    pub data: [u8; 32],
+}
+
+impl Share {
+    pub fn encode(&self) -> String {
+        let mut bytes = vec![SHARE_VERSION, self.threshold, self.index];
+        bytes.extend_from_slice(&self.fingerprint);
+        bytes.extend_from_slice(&self.data);
+        bech32::encode(SHARE_HRP, bytes.to_base32(), Variant::Bech32m).unwrap()
+    }
+
+    pub fn decode(s: &str) -> Result<Self, VoteError> {
+        let invalid = |m: &str| VoteError::InvalidShare(m.to_string());
+        let (hrp, data, variant) =
+            bech32::decode(s.trim()).map_err(|_| invalid("corrupted share"))?;
+        if hrp != SHARE_HRP || variant != Variant::Bech32m {
+            return Err(invalid("not a tally share"));
+        }
+        let bytes = Vec::<u8>::from_base32(&data).map_err(|_| invalid("corrupted share"))?;
+        if bytes.len() != 3 + FINGERPRINT_LEN + 32 {
+            return Err(invalid("incorrect length"));
+        }
+        if bytes[0] != SHARE_VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        let (threshold, index) = (bytes[1], bytes[2]);
+        if threshold == 0 || index == 0 {
+            return Err(invalid("invalid parameters"));
+        }
+        Ok(Share {
+            threshold,
+            index,
+            fingerprint: bytes[3..3 + FINGERPRINT_LEN].try_into().unwrap(),
+            data: bytes[3 + FINGERPRINT_LEN..].try_into().unwrap(),
+        })
+    }
+}
+
+fn fingerprint(secret: &[u8; 32]) -> [u8; FINGERPRINT_LEN] {
+    let hash = Params::new()
+        .hash_length(FINGERPRINT_LEN)
+        .personal(b"ZcashVote_Shares")
+        .hash(secret);
+    hash.as_bytes().try_into().unwrap()
+}
+
+/// Multiplication in GF(256) with the AES polynomial
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut r = 0u8;
+    while b != 0 {
+        if b & 1 != 0 {
+            r ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1B;
+        }
+        b >>= 1;
+    }
+    r
+}
+
+/// a^254 = a^-1 for a != 0
+fn gf_inv(a: u8) -> u8 {
+    let mut r = 1u8;
+    let mut base = a;
+    let mut e = 254u8;
+    while e != 0 {
+        if e & 1 != 0 {
+            r = gf_mul(r, base);
+        }
+        base = gf_mul(base, base);
+        e >>= 1;
+    }
+    r
+}
+
+/// Split a secret into `count` shares, any `threshold` of them
+/// can reconstruct it
+pub fn split_secret(secret: &[u8; 32], threshold: u8, count: u8) -> Result<Vec<Share>, VoteError> {
+    if threshold == 0 || threshold > count {
+        return Err(VoteError::InvalidShare(format!(
+            "cannot make {threshold} of {count} shares"
+        )));
+    }
+    let fingerprint = fingerprint(secret);
+    // coefficients[0] is the secret
+    let mut coefficients = vec![[0u8; 32]; threshold as usize];
+    coefficients[0] = *secret;
+    for c in coefficients.iter_mut().skip(1) {
+        OsRng.fill_bytes(c);
+    }
+
+    let shares = (1..=count)
+        .map(|x| {
+            let mut data = [0u8; 32];
+            for (j, d) in data.iter_mut().enumerate() {
+                // Horner's rule
+                *d = coefficients
+                    .iter()
+                    .rev()
+                    .fold(0u8, |acc, c| gf_mul(acc, x) ^ c[j]);
+            }
+            Share {
+                threshold,
+                index: x,
+                fingerprint,
+                data,
+            }
+        })
+        .collect();
+    Ok(shares)
+}
+
+/// Reconstruct the secret from at least `threshold` distinct shares
+pub fn combine_shares(shares: &[Share]) -> Result<[u8; 32], VoteError> {
+    let invalid = |m: String| Err(VoteError::InvalidShare(m));
+    let Some(first) = shares.first() else {
+        return invalid("no shares".to_string());
+    };
+    let mut selected: Vec<&Share> = vec![];
+    for s in shares.iter() {
+        if s.threshold != first.threshold || s.fingerprint != first.fingerprint {
+            return invalid(format!("share #{} is from another secret", s.index));
+        }
+        match selected.iter().find(|t| t.index == s.index) {
+            Some(t) if t.data != s.data => {
+                return invalid(format!("conflicting shares #{}", s.index));
+            }
+            Some(_) => {}
+            None => selected.push(s),
+        }
+    }
+    let threshold = first.threshold as usize;
+    if selected.len() < threshold {
+        return invalid(format!(
+            "{} shares are required, only {} given",
+            threshold,
+            selected.len()
+        ));
+    }
+    let selected = &selected[..threshold];
+
+    // Lagrange interpolation at x = 0
+    let mut secret = [0u8; 32];
+    for (i, si) in selected.iter().enumerate() {
+        let mut l = 1u8;
+        for (j, sj) in selected.iter().enumerate() {
+            if i != j {
+                l = gf_mul(l, gf_mul(sj.index, gf_inv(sj.index ^ si.index)));
+            }
+        }
+        for (d, v) in secret.iter_mut().zip(si.data.iter()) {
+            *d ^= gf_mul(l, *v);
+        }
+    }
+
+    if fingerprint(&secret) != first.fingerprint {
+        return invalid("shares do not match the secret".to_string());
+    }
+    Ok(secret)
+}
+
+/// Split the entropy of a 24 word seed phrase into encoded shares
+pub fn split_seed(phrase: &str, threshold: u8, count: u8) -> Result<Vec<String>, VoteError> {
+    let mnemonic = Mnemonic::from_phrase(phrase)
+        .map_err(|e| VoteError::InvalidShare(e.to_string()))?;
+    let entropy: &[u8; 32] = mnemonic
+        .entropy()
+        .try_into()
+        .map_err(|_| VoteError::InvalidShare("seed must have 24 words".to_string()))?;
+    let shares = split_secret(entropy, threshold, count)?;
+    Ok(shares.iter().map(Share::encode).collect())
+}
+
+/// Recover the seed phrase from encoded shares
+pub fn combine_seed(shares: &[String]) -> Result<String, VoteError> {
+    let shares = shares
+        .iter()
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| Share::decode(s))
+        .collect::<Result<Vec<_>, _>>()?;
+    let entropy = combine_shares(&shares)?;
+    let mnemonic = Mnemonic::from_entropy(entropy.to_vec())
+        .map_err(|e| VoteError::InvalidShare(e.to_string()))?;
+    Ok(mnemonic.phrase().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret() -> [u8; 32] {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        secret
+    }
+
+    #[test]
+    fn any_threshold_subset_recovers_the_secret() {
+        let secret = secret();
+        let shares = split_secret(&secret, 3, 5).unwrap();
+        for subset in [[0, 1, 2], [0, 2, 4], [4, 3, 1]] {
+            let subset = subset.map(|i| shares[i].clone());
+            assert_eq!(combine_shares(&subset).unwrap(), secret);
+        }
+    }
+
+    #[test]
+    fn insufficient_shares_are_rejected() {
+        let shares = split_secret(&secret(), 3, 5).unwrap();
+        assert!(combine_shares(&shares[..2]).is_err());
+        // duplicates do not count twice
+        let duplicated = [shares[0].clone(), shares[0].clone(), shares[1].clone()];
+        assert!(combine_shares(&duplicated).is_err());
+    }
+
+    #[test]
+    fn corrupted_shares_are_rejected() {
+        let shares = split_secret(&secret(), 2, 3).unwrap();
+        let encoded = shares[0].encode();
+        let mut corrupted = encoded.clone().into_bytes();
+        let last = corrupted.len() - 1;
+        corrupted[last] = if corrupted[last] == b'q' { b'p' } else { b'q' };
+        assert!(Share::decode(&String::from_utf8(corrupted).unwrap()).is_err());
+
+        // valid encoding but altered data
+        let mut altered = shares[0].clone();
+        altered.data[0] ^= 1;
+        assert!(combine_shares(&[altered, shares[1].clone()]).is_err());
+
+        // shares of another secret
+        let other = split_secret(&secret(), 2, 3).unwrap();
+        assert!(combine_shares(&[shares[0].clone(), other[1].clone()]).is_err());
+    }
+
+    #[test]
+    fn seed_round_trip() {
+        let mnemonic = Mnemonic::generate(bip0039::Count::Words24);
+        let shares = split_seed(mnemonic.phrase(), 2, 3).unwrap();
+        let phrase = combine_seed(&shares[1..]).unwrap();
+        assert_eq!(phrase, mnemonic.phrase());
+    }
+}
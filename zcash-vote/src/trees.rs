@@ -3,9 +3,9 @@ use orchard::vote::{calculate_merkle_paths, Frontier, OrchardHash};
 use pasta_curves::{group::ff::PrimeField as _, Fp};
 use rusqlite::Connection;
 
-pub fn list_nf_ranges(connection: &Connection) -> Result<Vec<Fp>> {
-    let mut s = connection.prepare("SELECT hash FROM nfs")?;
-    let rows = s.query_map([], |r| {
+pub fn list_nf_ranges(connection: &Connection, id_election: u32) -> Result<Vec<Fp>> {
+    let mut s = connection.prepare("SELECT hash FROM nfs WHERE election = ?1")?;
+    let rows = s.query_map([id_election], |r| {
         let v = r.get::<_, [u8; 32]>(0)?;
         let v = Fp::from_repr(v).unwrap();
         Ok(v)
@@ -16,16 +16,17 @@ pub fn list_nf_ranges(connection: &Connection) -> Result<Vec<Fp>> {
     Ok(nf_tree)
 }
 
-pub fn compute_nf_root(connection: &Connection) -> Result<OrchardHash> {
-    let nf_tree = list_nf_ranges(connection)?;
+pub fn compute_nf_root(connection: &Connection, id_election: u32) -> Result<OrchardHash> {
+    let nf_tree = list_nf_ranges(connection, id_election)?;
     let (nf_root, _) = calculate_merkle_paths(0, &[], &nf_tree);
 
     Ok(OrchardHash(nf_root.to_repr()))
 }
 
-pub fn list_cmxs(connection: &Connection) -> Result<Vec<Fp>> {
-    let mut s = connection.prepare("SELECT hash FROM cmxs ORDER BY id_cmx")?;
-    let rows = s.query_map([], |r| {
+pub fn list_cmxs(connection: &Connection, id_election: u32) -> Result<Vec<Fp>> {
+    let mut s =
+        connection.prepare("SELECT hash FROM cmxs WHERE election = ?1 ORDER BY id_cmx")?;
+    let rows = s.query_map([id_election], |r| {
         let v = r.get::<_, [u8; 32]>(0)?;
         let v = Fp::from_repr(v).unwrap();
         Ok(v)
@@ -34,8 +35,11 @@ pub fn list_cmxs(connection: &Connection) -> Result<Vec<Fp>> {
     Ok(cmx_tree)
 }
 
-pub fn compute_cmx_root(connection: &Connection) -> Result<(OrchardHash, Option<Frontier>)> {
-    let cmx_tree = list_cmxs(connection)?;
+pub fn compute_cmx_root(
+    connection: &Connection,
+    id_election: u32,
+) -> Result<(OrchardHash, Option<Frontier>)> {
+    let cmx_tree = list_cmxs(connection, id_election)?;
     let (cmx_root, frontier) = if cmx_tree.is_empty() {
         let (cmx_root, _) = calculate_merkle_paths(0, &[], &[]);
         (cmx_root, None)